@@ -1,5 +1,5 @@
 use crate::ray::Ray;
-use crate::vec3::Point3;
+use crate::vec3::{Point3, Vec3};
 
 #[derive(Clone, Copy, Debug, Default)]
 pub struct Aabb {
@@ -27,6 +27,13 @@ impl Aabb {
     pub fn maximum(&self) -> Point3 {
         self.maximum
     }
+    pub fn centroid(&self) -> Point3 {
+        0.5 * (self.minimum + self.maximum)
+    }
+    pub fn surface_area(&self) -> f64 {
+        let d = self.maximum - self.minimum;
+        2.0 * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
 
     pub fn hit(&self, r: &Ray, mut t_min: f64, mut t_max: f64) -> bool {
         for a in 0..3 {
@@ -54,6 +61,30 @@ impl Aabb {
         true
     }
 
+    // `hit` with the reciprocal of the ray direction computed once, for BVH
+    // traversal.
+    pub fn hit_inverse(
+        &self,
+        origin: &Point3,
+        inv_dir: &Vec3,
+        mut t_min: f64,
+        mut t_max: f64,
+    ) -> bool {
+        for a in 0..3 {
+            let mut t0 = (self.minimum.e[a] - origin.e[a]) * inv_dir.e[a];
+            let mut t1 = (self.maximum.e[a] - origin.e[a]) * inv_dir.e[a];
+            if inv_dir.e[a] < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_max <= t_min {
+                return false;
+            }
+        }
+        true
+    }
+
     pub fn surrounding_box(box0: &Self, box1: &Self) -> Aabb {
         let small: Point3 = Point3::construct(&[
             box0.minimum().x().min(box1.minimum().x()),
@@ -2,9 +2,23 @@ use crate::aabb::Aabb;
 use crate::hittable::{HitRecord, Hittable};
 use crate::material::Material;
 use crate::ray::Ray;
-use crate::vec3::{Point3, Vec3};
+use crate::rt_weekend::INFINITY;
+use crate::sampler::sample_2d;
+use crate::vec3::{dot, Point3, Vec3};
 use std::sync::Arc;
 
+// Converts the density of a uniformly sampled point on a surface of `area` into
+// a density over the directions `v` that reach the surface at ray parameter
+// `t`, where its geometric normal is `normal`.
+pub fn light_pdf(v: &Vec3, t: f64, normal: &Vec3, area: f64) -> f64 {
+    let distance_squared = t * t * v.length_squared();
+    let cosine = dot(v, normal).abs() / v.length();
+    if cosine <= 0.0 {
+        return 0.0;
+    }
+    distance_squared / (cosine * area)
+}
+
 pub struct XyRect {
     pub mp: Arc<dyn Material>,
     pub x0: f64,
@@ -24,18 +38,27 @@ impl XyRect {
             k,
         }
     }
-}
-impl Hittable for XyRect {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    // The ray parameter and the x and y of the point where the ray meets
+    // the rectangle, if in (t_min, t_max).
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
         let t: f64 = (self.k - r.origin().z()) / r.direction().z();
         if t < t_min || t > t_max {
-            return false;
+            return None;
         }
         let x: f64 = r.origin().x() + t * r.direction().x();
         let y: f64 = r.origin().y() + t * r.direction().y();
         if x < self.x0 || x > self.x1 || y < self.y0 || y > self.y1 {
-            return false;
+            return None;
         }
+        Some((t, x, y))
+    }
+}
+impl Hittable for XyRect {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let (t, x, y) = match self.intersect(r, t_min, t_max) {
+            Some(hit) => hit,
+            None => return false,
+        };
         rec.u = (x - self.x0) / (self.x1 - self.x0);
         rec.v = (y - self.y0) / (self.y1 - self.y0);
         rec.t = t;
@@ -44,6 +67,9 @@ impl Hittable for XyRect {
         rec.p = r.at(t);
         true
     }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
+    }
     fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
         // non-zero width in each dimension
         *output_box = Aabb::construct(
@@ -52,6 +78,26 @@ impl Hittable for XyRect {
         );
         true
     }
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new();
+        if !self.hit(&Ray::construct(o, v, 0.0), 0.001, INFINITY, &mut rec) {
+            return 0.0;
+        }
+        let area = (self.x1 - self.x0) * (self.y1 - self.y0);
+        light_pdf(v, rec.t, &rec.normal, area)
+    }
+    fn random(&self, o: &Point3) -> Vec3 {
+        let (a, b) = sample_2d();
+        let random_point = Point3::construct(&[
+            self.x0 + a * (self.x1 - self.x0),
+            self.y0 + b * (self.y1 - self.y0),
+            self.k,
+        ]);
+        random_point - *o
+    }
+    fn is_light(&self) -> bool {
+        self.mp.is_emissive()
+    }
 }
 
 pub struct XzRect {
@@ -73,18 +119,27 @@ impl XzRect {
             k,
         }
     }
-}
-impl Hittable for XzRect {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    // The ray parameter and the x and z of the point where the ray meets
+    // the rectangle, if in (t_min, t_max).
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
         let t: f64 = (self.k - r.origin().y()) / r.direction().y();
         if t < t_min || t > t_max {
-            return false;
+            return None;
         }
         let x: f64 = r.origin().x() + t * r.direction().x();
         let z: f64 = r.origin().z() + t * r.direction().z();
         if x < self.x0 || x > self.x1 || z < self.z0 || z > self.z1 {
-            return false;
+            return None;
         }
+        Some((t, x, z))
+    }
+}
+impl Hittable for XzRect {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let (t, x, z) = match self.intersect(r, t_min, t_max) {
+            Some(hit) => hit,
+            None => return false,
+        };
         rec.u = (x - self.x0) / (self.x1 - self.x0);
         rec.v = (z - self.z0) / (self.z1 - self.z0);
         rec.t = t;
@@ -93,6 +148,9 @@ impl Hittable for XzRect {
         rec.p = r.at(t);
         true
     }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
+    }
     fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
         // non-zero width in each dimension
         *output_box = Aabb::construct(
@@ -101,6 +159,26 @@ impl Hittable for XzRect {
         );
         true
     }
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new();
+        if !self.hit(&Ray::construct(o, v, 0.0), 0.001, INFINITY, &mut rec) {
+            return 0.0;
+        }
+        let area = (self.x1 - self.x0) * (self.z1 - self.z0);
+        light_pdf(v, rec.t, &rec.normal, area)
+    }
+    fn random(&self, o: &Point3) -> Vec3 {
+        let (a, b) = sample_2d();
+        let random_point = Point3::construct(&[
+            self.x0 + a * (self.x1 - self.x0),
+            self.k,
+            self.z0 + b * (self.z1 - self.z0),
+        ]);
+        random_point - *o
+    }
+    fn is_light(&self) -> bool {
+        self.mp.is_emissive()
+    }
 }
 
 pub struct YzRect {
@@ -122,18 +200,27 @@ impl YzRect {
             k,
         }
     }
-}
-impl Hittable for YzRect {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    // The ray parameter and the y and z of the point where the ray meets
+    // the rectangle, if in (t_min, t_max).
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
         let t: f64 = (self.k - r.origin().x()) / r.direction().x();
         if t < t_min || t > t_max {
-            return false;
+            return None;
         }
         let y: f64 = r.origin().y() + t * r.direction().y();
         let z: f64 = r.origin().z() + t * r.direction().z();
         if y < self.y0 || y > self.y1 || z < self.z0 || z > self.z1 {
-            return false;
+            return None;
         }
+        Some((t, y, z))
+    }
+}
+impl Hittable for YzRect {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let (t, y, z) = match self.intersect(r, t_min, t_max) {
+            Some(hit) => hit,
+            None => return false,
+        };
         rec.u = (y - self.y0) / (self.y1 - self.y0);
         rec.v = (z - self.z0) / (self.z1 - self.z0);
         rec.t = t;
@@ -142,6 +229,9 @@ impl Hittable for YzRect {
         rec.p = r.at(t);
         true
     }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
+    }
     fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
         // non-zero width in each dimension
         *output_box = Aabb::construct(
@@ -150,4 +240,24 @@ impl Hittable for YzRect {
         );
         true
     }
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new();
+        if !self.hit(&Ray::construct(o, v, 0.0), 0.001, INFINITY, &mut rec) {
+            return 0.0;
+        }
+        let area = (self.y1 - self.y0) * (self.z1 - self.z0);
+        light_pdf(v, rec.t, &rec.normal, area)
+    }
+    fn random(&self, o: &Point3) -> Vec3 {
+        let (a, b) = sample_2d();
+        let random_point = Point3::construct(&[
+            self.k,
+            self.y0 + a * (self.y1 - self.y0),
+            self.z0 + b * (self.z1 - self.z0),
+        ]);
+        random_point - *o
+    }
+    fn is_light(&self) -> bool {
+        self.mp.is_emissive()
+    }
 }
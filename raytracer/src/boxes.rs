@@ -76,8 +76,14 @@ impl Hittable for Box_ {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         self.sides.hit(r, t_min, t_max, rec)
     }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.sides.occluded(r, t_min, t_max)
+    }
     fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
         *output_box = Aabb::construct(&self.box_min, &self.box_max);
         true
     }
+    fn collect_lights(&self, lights: &mut HittableList) {
+        self.sides.collect_lights(lights);
+    }
 }
@@ -1,119 +1,492 @@
-use crate::hittable::{HitRecord, Hittable};
-use crate::rt_weekend::random_int;
+use crate::hittable::{collect_lights_of, HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::rt_weekend::{random_int, INFINITY};
+use crate::vec3::{Point3, Vec3};
 use crate::{aabb::Aabb, hittable_list::HittableList};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use std::sync::Arc;
 
+// How BVH builders split a set of primitives in two.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SplitMethod {
+    // Binned surface area heuristic over the primitive centroids, on the
+    // best of the three axes.
+    Sah,
+    // The median of the box minimums along a random axis.
+    RandomMedian,
+}
+
+impl SplitMethod {
+    pub const NAMES: [&'static str; 2] = ["sah", "median"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sah" => Some(Self::Sah),
+            "median" => Some(Self::RandomMedian),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SplitMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Sah => write!(f, "sah"),
+            Self::RandomMedian => write!(f, "median"),
+        }
+    }
+}
+
+// Scenes build their BVHs wherever they like, so the builder settings are
+// global rather than passed down to every `BVHNode::new`.
+static SPLIT_METHOD: AtomicU8 = AtomicU8::new(SplitMethod::Sah as u8);
+static REPORT_STATS: AtomicBool = AtomicBool::new(false);
+
+// Applies to the BVHs built afterwards. With `report_stats`, each one prints
+// its `BvhStats` once built.
+pub fn configure(method: SplitMethod, report_stats: bool) {
+    SPLIT_METHOD.store(method as u8, Ordering::Relaxed);
+    REPORT_STATS.store(report_stats, Ordering::Relaxed);
+}
+
+fn split_method() -> SplitMethod {
+    if SPLIT_METHOD.load(Ordering::Relaxed) == SplitMethod::Sah as u8 {
+        SplitMethod::Sah
+    } else {
+        SplitMethod::RandomMedian
+    }
+}
+
+// Reorders `items` so that `items[..mid]` and `items[mid..]` are the two
+// halves, and returns `mid` and the axis along which the first half comes
+// first. There must be at least two items; both halves are non-empty.
+fn split<T>(items: &mut [T], bbox: impl Fn(&T) -> Aabb) -> (usize, usize) {
+    match split_method() {
+        SplitMethod::Sah => sah_split(items, &bbox),
+        SplitMethod::RandomMedian => {
+            let axis = random_int(0, 2) as usize;
+            items.sort_unstable_by(|a, b| {
+                let min_a = bbox(a).minimum().e[axis];
+                let min_b = bbox(b).minimum().e[axis];
+                f64::partial_cmp(&min_a, &min_b).unwrap()
+            });
+            (items.len() / 2, axis)
+        }
+    }
+}
+
+const SAH_BINS: usize = 16;
+
+// Bins the centroids along each axis and takes the bin boundary with the
+// lowest area(left) * count(left) + area(right) * count(right).
+fn sah_split<T>(items: &mut [T], bbox: &impl Fn(&T) -> Aabb) -> (usize, usize) {
+    let mut min = Point3::construct(&[INFINITY, INFINITY, INFINITY]);
+    let mut max = Point3::construct(&[-INFINITY, -INFINITY, -INFINITY]);
+    for item in items.iter() {
+        let c = bbox(item).centroid();
+        for a in 0..3 {
+            min.e[a] = min.e[a].min(c.e[a]);
+            max.e[a] = max.e[a].max(c.e[a]);
+        }
+    }
+    let bin_of = |item: &T, axis: usize| {
+        let extent = max.e[axis] - min.e[axis];
+        let offset = (bbox(item).centroid().e[axis] - min.e[axis]) / extent;
+        ((offset * SAH_BINS as f64) as usize).min(SAH_BINS - 1)
+    };
+
+    // (cost, axis, first bin of the right half)
+    let mut best: Option<(f64, usize, usize)> = None;
+    for axis in 0..3 {
+        let extent = max.e[axis] - min.e[axis];
+        if extent <= 0.0 || !extent.is_finite() {
+            continue;
+        }
+        let mut counts = [0; SAH_BINS];
+        let mut boxes: [Option<Aabb>; SAH_BINS] = [None; SAH_BINS];
+        for item in items.iter() {
+            let b = bin_of(item, axis);
+            let item_box = bbox(item);
+            counts[b] += 1;
+            boxes[b] = Some(boxes[b].map_or(item_box, |bb| Aabb::surrounding_box(&bb, &item_box)));
+        }
+
+        // The cost of everything from bin `i` on, swept from the right.
+        let mut right_cost = [0.0; SAH_BINS];
+        let mut right_count = [0; SAH_BINS];
+        let mut acc: Option<Aabb> = None;
+        let mut n = 0;
+        for i in (1..SAH_BINS).rev() {
+            acc = merge(acc, boxes[i]);
+            n += counts[i];
+            right_cost[i] = acc.map_or(0.0, |b| b.surface_area() * n as f64);
+            right_count[i] = n;
+        }
+        let mut acc: Option<Aabb> = None;
+        let mut n = 0;
+        for i in 1..SAH_BINS {
+            acc = merge(acc, boxes[i - 1]);
+            n += counts[i - 1];
+            if n == 0 || right_count[i] == 0 {
+                continue;
+            }
+            let cost = acc.map_or(0.0, |b| b.surface_area() * n as f64) + right_cost[i];
+            let better = match best {
+                Some((best_cost, _, _)) => cost < best_cost,
+                None => true,
+            };
+            if better {
+                best = Some((cost, axis, i));
+            }
+        }
+    }
+
+    match best {
+        Some((_, axis, first_right)) => {
+            let mut mid = 0;
+            for i in 0..items.len() {
+                if bin_of(&items[i], axis) < first_right {
+                    items.swap(i, mid);
+                    mid += 1;
+                }
+            }
+            (mid, axis)
+        }
+        // The centroids all coincide, any split is as good as another.
+        None => (items.len() / 2, 0),
+    }
+}
+
+fn merge(a: Option<Aabb>, b: Option<Aabb>) -> Option<Aabb> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(Aabb::surrounding_box(&a, &b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+// Shape of a built BVH, to compare split methods.
+#[derive(Clone, Debug, Default)]
+pub struct BvhStats {
+    pub inner_nodes: usize,
+    pub leaves: usize,
+    pub primitives: usize,
+    pub max_depth: usize,
+    pub min_leaf_size: usize,
+    pub max_leaf_size: usize,
+    // Sum of the leaf depths.
+    depth_sum: usize,
+    // Surface areas of the inner nodes, and of the leaves times their sizes.
+    inner_area: f64,
+    leaf_area: f64,
+    root_area: f64,
+}
+
+impl BvhStats {
+    // Expected cost of a random ray through the root: each node is reached
+    // with probability area / root area, a node costs 1 to traverse and a
+    // primitive 1 to intersect.
+    pub fn sah_cost(&self) -> f64 {
+        if self.root_area > 0.0 {
+            (self.inner_area + self.leaf_area) / self.root_area
+        } else {
+            (self.inner_nodes + self.primitives) as f64
+        }
+    }
+
+    // The root is added first.
+    fn add_node(&mut self, bbox: &Aabb) {
+        if self.inner_nodes + self.leaves == 0 {
+            self.root_area = bbox.surface_area();
+        }
+    }
+    fn add_inner(&mut self, bbox: &Aabb) {
+        self.add_node(bbox);
+        self.inner_nodes += 1;
+        self.inner_area += bbox.surface_area();
+    }
+    fn add_leaf(&mut self, bbox: &Aabb, size: usize, depth: usize) {
+        self.add_node(bbox);
+        if self.leaves == 0 || size < self.min_leaf_size {
+            self.min_leaf_size = size;
+        }
+        self.leaves += 1;
+        self.primitives += size;
+        self.max_depth = self.max_depth.max(depth);
+        self.max_leaf_size = self.max_leaf_size.max(size);
+        self.depth_sum += depth;
+        self.leaf_area += bbox.surface_area() * size as f64;
+    }
+
+    fn report(&self) {
+        if REPORT_STATS.load(Ordering::Relaxed) {
+            println!("BVH ({}): {}", split_method(), self);
+        }
+    }
+}
+
+impl fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} primitives, {} inner nodes, {} leaves of {} to {} (mean {:.2}), depth {} (mean {:.1}), SAH cost {:.2}",
+            self.primitives,
+            self.inner_nodes,
+            self.leaves,
+            self.min_leaf_size,
+            self.max_leaf_size,
+            self.primitives as f64 / self.leaves.max(1) as f64,
+            self.max_depth,
+            self.depth_sum as f64 / self.leaves.max(1) as f64,
+            self.sah_cost()
+        )
+    }
+}
+
+// Bounding volume hierarchy over a list of objects, which are held in a flat
+// array with the tree over their indices.
 pub struct BVHNode {
-    pub left: Option<Arc<dyn Hittable>>,
-    pub right: Option<Arc<dyn Hittable>>,
-    pub box_: Aabb,
-} // bounding volume hierachy
+    objects: Vec<Arc<dyn Hittable>>,
+    bvh: IndexBvh,
+}
 
 impl BVHNode {
+    // Objects are dynamic calls away, so keep the leaves small.
+    const LEAF_SIZE: usize = 2;
+
     pub fn new(list: &HittableList, time0: f64, time1: f64) -> Self {
         Self::construct(&list.objects, time0, time1)
     }
     pub fn construct(objects: &[Arc<dyn Hittable>], time0: f64, time1: f64) -> Self {
         // println!("{}", objects.len());
-        let mut src_objects = objects.to_owned();
-        let left;
-        let right;
-        let axis = random_int(0, 2) as usize;
-        let obj_len = objects.len();
+        let boxes: Vec<Aabb> = objects
+            .iter()
+            .map(|object| {
+                let mut bbox = Aabb::new();
+                if !object.bounding_box(time0, time1, &mut bbox) {
+                    panic!("BVH: No bounding box");
+                }
+                bbox
+            })
+            .collect();
+        Self {
+            objects: objects.to_owned(),
+            bvh: IndexBvh::construct(&boxes, Self::LEAF_SIZE),
+        }
+    }
+}
 
-        if obj_len == 0 {
+impl Hittable for BVHNode {
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
+        *output_box = self.bvh.bounding_box();
+        true
+    }
+    fn collect_lights(&self, lights: &mut HittableList) {
+        for object in &self.objects {
+            collect_lights_of(object, lights);
+        }
+    }
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        // Objects only write to `rec` when hit, and only closer hits than the
+        // last, so it ends up with the closest.
+        self.bvh.hit(r, t_min, t_max, &mut |index, closest| {
+            if self.objects[index].hit(r, t_min, closest, rec) {
+                Some(rec.t)
+            } else {
+                None
+            }
+        })
+    }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.bvh.occluded(r, t_min, t_max, &mut |index| {
+            self.objects[index].occluded(r, t_min, t_max)
+        })
+    }
+}
+
+// A node of an `IndexBvh`. Leaves hold `count` primitives starting at `first`
+// in `IndexBvh::indices`; inner nodes have `count == 0` and their two children
+// at `first` and `first + 1`, the first one lower along `axis`.
+#[derive(Clone, Copy, Debug)]
+pub struct IndexNode {
+    pub bbox: Aabb,
+    pub first: u32,
+    pub count: u32,
+    pub axis: u8,
+}
+
+// A BVH over primitives known by their index, as a flat array of nodes with
+// the children of a node next to each other.
+pub struct IndexBvh {
+    pub nodes: Vec<IndexNode>,
+    pub indices: Vec<u32>,
+}
+
+impl IndexBvh {
+    // Deeper nodes are made leaves whatever their size, which bounds the
+    // traversal stack.
+    const MAX_DEPTH: usize = 64;
+
+    // `boxes[i]` bounds primitive `i`; there must be at least one. Nodes are
+    // split until they hold at most `max_leaf_size` primitives.
+    pub fn construct(boxes: &[Aabb], max_leaf_size: usize) -> Self {
+        if boxes.is_empty() {
             panic!("BVH: Empty list");
         }
+        let mut bvh = Self {
+            nodes: vec![IndexNode {
+                bbox: Aabb::new(),
+                first: 0,
+                count: 0,
+                axis: 0,
+            }],
+            indices: (0..boxes.len() as u32).collect(),
+        };
+        bvh.build(0, 0, boxes.len(), 0, boxes, max_leaf_size.max(1));
+        bvh.stats().report();
+        bvh
+    }
+
+    fn build(
+        &mut self,
+        node: usize,
+        start: usize,
+        end: usize,
+        depth: usize,
+        boxes: &[Aabb],
+        max_leaf_size: usize,
+    ) {
+        let prims = &mut self.indices[start..end];
+        let mut bbox = boxes[prims[0] as usize];
+        for &i in prims.iter() {
+            bbox = Aabb::surrounding_box(&bbox, &boxes[i as usize]);
+        }
+        self.nodes[node].bbox = bbox;
+        if prims.len() <= max_leaf_size || depth + 1 >= Self::MAX_DEPTH {
+            self.nodes[node].first = start as u32;
+            self.nodes[node].count = prims.len() as u32;
+            return;
+        }
 
-        if obj_len == 1 {
-            left = Some(src_objects.remove(0));
-            // pop, 往左移
-            right = None;
-        } else {
-            src_objects.sort_unstable_by(|a, b| {
-                let mut aabb_a = Aabb::new();
-                let mut aabb_b = Aabb::new();
-                let bool_a = a.bounding_box(time0, time1, &mut aabb_a);
-                let bool_b = b.bounding_box(time0, time1, &mut aabb_b);
-                if !bool_a || !bool_b {
-                    panic!("fuck you");
-                }
-                let min_a = aabb_a.minimum().e[axis];
-                let min_b = aabb_b.minimum().e[axis];
-                f64::partial_cmp(&min_a, &min_b).unwrap()
-            });
-            if obj_len == 2 {
-                right = Some(src_objects.remove(1));
-                left = Some(src_objects.remove(0));
+        let (mid, axis) = split(prims, |&i| boxes[i as usize]);
+        let mid = start + mid;
+        let left = self.nodes.len();
+        self.nodes[node].first = left as u32;
+        self.nodes[node].axis = axis as u8;
+        self.nodes.push(self.nodes[node]);
+        self.nodes.push(self.nodes[node]);
+        self.build(left, start, mid, depth + 1, boxes, max_leaf_size);
+        self.build(left + 1, mid, end, depth + 1, boxes, max_leaf_size);
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        self.nodes[0].bbox
+    }
+
+    pub fn stats(&self) -> BvhStats {
+        let mut stats = BvhStats::default();
+        let mut stack = vec![(0, 0)];
+        while let Some((node, depth)) = stack.pop() {
+            let n = &self.nodes[node];
+            if n.count == 0 {
+                stats.add_inner(&n.bbox);
+                stack.push((n.first as usize, depth + 1));
+                stack.push((n.first as usize + 1, depth + 1));
             } else {
-                let objects2 = src_objects.split_off(obj_len / 2);
-                left = Some(Arc::new(Self::construct(&src_objects, time0, time1)));
-                right = Some(Arc::new(Self::construct(&objects2, time0, time1)));
+                stats.add_leaf(&n.bbox, n.count as usize, depth);
             }
         }
-        let mut box_left = Aabb::new();
-        left.as_ref()
-            .unwrap()
-            .bounding_box(time0, time1, &mut box_left);
-        let box_ = if right.is_some() {
-            let mut box_right = Aabb::new();
-            right
-                .as_ref()
-                .unwrap()
-                .bounding_box(time0, time1, &mut box_right);
-            Aabb::surrounding_box(&box_left, &box_right)
-        } else {
-            box_left
-        };
-        Self { left, right, box_ }
+        stats
     }
-}
 
-impl Hittable for BVHNode {
-    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
-        *output_box = self.box_;
-        true
+    // `hit_primitive(i, closest)` intersects primitive `i` up to `closest` and
+    // returns the distance of the hit, having recorded it. Returns whether any
+    // primitive was hit.
+    pub fn hit<F>(&self, r: &Ray, t_min: f64, t_max: f64, hit_primitive: &mut F) -> bool
+    where
+        F: FnMut(usize, f64) -> Option<f64>,
+    {
+        let origin = r.origin();
+        let direction = r.direction();
+        let inv_dir = Vec3::construct(&[
+            1.0 / direction.x(),
+            1.0 / direction.y(),
+            1.0 / direction.z(),
+        ]);
+        let mut closest = t_max;
+        let mut hit_anything = false;
+        // Far children still to visit.
+        let mut stack = [0u32; Self::MAX_DEPTH];
+        let mut stack_len = 0;
+        let mut node = 0;
+        loop {
+            let n = &self.nodes[node];
+            if n.bbox.hit_inverse(&origin, &inv_dir, t_min, closest) {
+                if n.count == 0 {
+                    // Near child first, its hits can cull the far one.
+                    let (near, far) = if inv_dir.e[n.axis as usize] < 0.0 {
+                        (n.first + 1, n.first)
+                    } else {
+                        (n.first, n.first + 1)
+                    };
+                    stack[stack_len] = far;
+                    stack_len += 1;
+                    node = near as usize;
+                    continue;
+                }
+                for &i in &self.indices[n.first as usize..(n.first + n.count) as usize] {
+                    if let Some(t) = hit_primitive(i as usize, closest) {
+                        closest = t;
+                        hit_anything = true;
+                    }
+                }
+            }
+            if stack_len == 0 {
+                break;
+            }
+            stack_len -= 1;
+            node = stack[stack_len] as usize;
+        }
+        hit_anything
     }
-    fn hit(&self, r: &crate::ray::Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
-        if !self.box_.hit(r, t_min, t_max) {
-            return false;
-        }
-        // println!("BVH: hit");
-        let mut t_max_mut = t_max;
-        *rec = HitRecord::new();
-        let mut tmp_rec = HitRecord::new();
-        let mut ret = false;
-        if self
-            .left
-            .as_ref()
-            .unwrap()
-            .hit(r, t_min, t_max, &mut tmp_rec)
-        {
-            t_max_mut = tmp_rec.t;
-            *rec = tmp_rec.clone();
-            ret = true;
-        }
-        if self.right.is_some()
-            && self
-                .right
-                .as_ref()
-                .unwrap()
-                .hit(r, t_min, t_max_mut, &mut tmp_rec)
-        {
-            *rec = tmp_rec;
-            ret = true;
-        }
-        // if self.right.is_some() {
-        //     if self
-        //         .right
-        //         .as_ref()
-        //         .unwrap()
-        //         .hit(r, t_min, t_max_mut, &mut tmp_rec)
-        //     {
-        //         *rec = tmp_rec;
-        //         ret = true;
-        //     }
-        // }
-        ret
+
+    // Whether `occluded_by(i)` holds for any primitive `i` whose box the ray
+    // crosses in (t_min, t_max). Returns at the first one.
+    pub fn occluded<F>(&self, r: &Ray, t_min: f64, t_max: f64, occluded_by: &mut F) -> bool
+    where
+        F: FnMut(usize) -> bool,
+    {
+        let origin = r.origin();
+        let direction = r.direction();
+        let inv_dir = Vec3::construct(&[
+            1.0 / direction.x(),
+            1.0 / direction.y(),
+            1.0 / direction.z(),
+        ]);
+        // Any hit will do, so the children are visited in order.
+        let mut stack = [0u32; Self::MAX_DEPTH];
+        let mut stack_len = 0;
+        let mut node = 0;
+        loop {
+            let n = &self.nodes[node];
+            if n.bbox.hit_inverse(&origin, &inv_dir, t_min, t_max) {
+                if n.count == 0 {
+                    stack[stack_len] = n.first + 1;
+                    stack_len += 1;
+                    node = n.first as usize;
+                    continue;
+                }
+                let leaf = &self.indices[n.first as usize..(n.first + n.count) as usize];
+                if leaf.iter().any(|&i| occluded_by(i as usize)) {
+                    return true;
+                }
+            }
+            if stack_len == 0 {
+                return false;
+            }
+            stack_len -= 1;
+            node = stack[stack_len] as usize;
+        }
     }
 }
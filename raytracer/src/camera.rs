@@ -1,5 +1,6 @@
 use crate::ray::Ray;
-use crate::rt_weekend::{degrees_to_radians, random_double_range};
+use crate::rt_weekend::degrees_to_radians;
+use crate::sampler::sample_1d;
 use crate::vec3::{cross, random_in_unit_disk, Point3, Vec3};
 
 #[derive(Clone, Default)]
@@ -65,7 +66,7 @@ impl Camera {
             &(self.lower_left_corner + self.horizontal * s + self.vertical * t
                 - self.origin
                 - offset),
-            random_double_range(self.time0, self.time1),
+            self.time0 + (self.time1 - self.time0) * sample_1d(),
         )
     }
 }
@@ -0,0 +1,266 @@
+// Saves a render in progress after every pass, so that it survives a crash and
+// can go on later with more samples.
+use crate::cli::Options;
+use crate::film::Film;
+use crate::sampler::SamplerKind;
+use std::fmt;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 4] = b"RTCK";
+const VERSION: u32 = 3;
+
+// FNV-1a. Unlike `DefaultHasher` it is the same in every build, so checkpoints
+// made by one build can be resumed by another.
+struct Fnv64(u64);
+
+impl Fnv64 {
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for Fnv64 {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+// Identifies what a render draws: the scene (the contents of every file it was
+// read from, with the scene file, its models, materials and textures, and the
+// name of a built-in scene) and every setting that changes the samples.
+// The sample counts (but for stratified sampling) and how the work is split up
+// are left out, so a render can be resumed with more samples, passes of another
+// size, another adaptive error target or more threads.
+pub fn fingerprint(opts: &Options, sources: &[PathBuf]) -> u64 {
+    let mut hasher = Fnv64::new();
+    VERSION.hash(&mut hasher);
+    if opts.scene_file.is_none() {
+        opts.scene.hash(&mut hasher);
+    }
+    for source in sources {
+        std::fs::read(source).unwrap_or_default().hash(&mut hasher);
+    }
+    opts.image_width.hash(&mut hasher);
+    opts.image_height.hash(&mut hasher);
+    opts.aspect_ratio.to_bits().hash(&mut hasher);
+    opts.max_depth.hash(&mut hasher);
+    opts.roulette_depth.hash(&mut hasher);
+    opts.clamp.map(f64::to_bits).hash(&mut hasher);
+    opts.clamp_indirect.map(f64::to_bits).hash(&mut hasher);
+    opts.seed.hash(&mut hasher);
+    opts.light_sampling.hash(&mut hasher);
+    opts.sampler.to_string().hash(&mut hasher);
+    // The strata divide the most samples a pixel can take, so samples taken
+    // before and after a change to that would not line up.
+    if opts.sampler == SamplerKind::Stratified {
+        opts.max_samples.hash(&mut hasher);
+    }
+    opts.filter.kind.to_string().hash(&mut hasher);
+    opts.filter.radius.to_bits().hash(&mut hasher);
+    // Building the BVHs draws random numbers, which moves the samples.
+    opts.bvh_split.to_string().hash(&mut hasher);
+    hasher.finish()
+}
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(PathBuf, io::Error),
+    Invalid(PathBuf, &'static str),
+    // Made for another scene or other settings.
+    Mismatch(PathBuf),
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckpointError::Io(path, e) => {
+                write!(f, "cannot read checkpoint `{}`: {}", path.display(), e)
+            }
+            CheckpointError::Invalid(path, why) => {
+                write!(f, "`{}` is not a valid checkpoint: {}", path.display(), why)
+            }
+            CheckpointError::Mismatch(path) => write!(
+                f,
+                "checkpoint `{}` belongs to another scene or other render settings",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+// The header, then for every pixel its weighted radiance as three f64s, the sum
+// of its weights, the sum of the luminance of its own samples and of its
+// square, and its sample count, all little-endian. The passes
+// done so far are the state of the generator: every pass reseeds it from the
+// seed and its own index.
+fn write_checkpoint(file: &mut impl Write, film: &Film, fingerprint: u64) -> io::Result<()> {
+    file.write_all(MAGIC)?;
+    file.write_all(&VERSION.to_le_bytes())?;
+    file.write_all(&fingerprint.to_le_bytes())?;
+    file.write_all(&film.width.to_le_bytes())?;
+    file.write_all(&film.height.to_le_bytes())?;
+    file.write_all(&film.passes.to_le_bytes())?;
+    for index in 0..film.pixels.len() {
+        for channel in film.pixels[index].e {
+            file.write_all(&channel.to_le_bytes())?;
+        }
+        file.write_all(&film.weights[index].to_le_bytes())?;
+        file.write_all(&film.luminance[index].to_le_bytes())?;
+        file.write_all(&film.squares[index].to_le_bytes())?;
+        file.write_all(&film.samples[index].to_le_bytes())?;
+    }
+    file.flush()
+}
+
+// Writes next to `path` first, so a crash while saving leaves the previous
+// checkpoint intact.
+pub fn save(path: &Path, film: &Film, fingerprint: u64) -> io::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    write_checkpoint(
+        &mut BufWriter::new(File::create(&partial)?),
+        film,
+        fingerprint,
+    )?;
+    std::fs::rename(&partial, path)
+}
+
+fn read_u32(file: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    file.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(file: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    file.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub fn load(path: &Path, fingerprint: u64) -> Result<Film, CheckpointError> {
+    let io_error = |e| CheckpointError::Io(path.to_path_buf(), e);
+    let invalid = |why| CheckpointError::Invalid(path.to_path_buf(), why);
+    let mut file = BufReader::new(File::open(path).map_err(io_error)?);
+
+    let mut magic = [0; 4];
+    if file.read_exact(&mut magic).is_err() || &magic != MAGIC {
+        return Err(invalid("unknown file type"));
+    }
+    if read_u32(&mut file).map_err(io_error)? != VERSION {
+        return Err(invalid("unsupported version"));
+    }
+    if read_u64(&mut file).map_err(io_error)? != fingerprint {
+        return Err(CheckpointError::Mismatch(path.to_path_buf()));
+    }
+    let width = read_u32(&mut file).map_err(io_error)?;
+    let height = read_u32(&mut file).map_err(io_error)?;
+    let mut film = Film::new(width, height);
+    film.passes = read_u32(&mut file).map_err(io_error)?;
+    let truncated = |_| invalid("truncated pixel data");
+    for index in 0..film.pixels.len() {
+        for channel in film.pixels[index].e.iter_mut() {
+            *channel = f64::from_bits(read_u64(&mut file).map_err(truncated)?);
+        }
+        film.weights[index] = f64::from_bits(read_u64(&mut file).map_err(truncated)?);
+        film.luminance[index] = f64::from_bits(read_u64(&mut file).map_err(truncated)?);
+        film.squares[index] = f64::from_bits(read_u64(&mut file).map_err(truncated)?);
+        film.samples[index] = read_u32(&mut file).map_err(truncated)?;
+    }
+    if file.read(&mut [0]).map_err(io_error)? != 0 {
+        return Err(invalid("trailing data"));
+    }
+    Ok(film)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::Color3;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("raytracer-{}-{}", std::process::id(), name))
+    }
+
+    fn film() -> Film {
+        let mut film = Film::new(3, 2);
+        for index in 0..film.pixels.len() {
+            let x = index as f64;
+            film.pixels[index] = Color3::construct(&[x, 0.5 * x, -x]);
+            film.weights[index] = 1.0 + x;
+            film.luminance[index] = 0.25 * x;
+            film.squares[index] = x * x;
+            film.samples[index] = index as u32 * 3;
+        }
+        film.passes = 7;
+        film
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = temp_path("round-trip");
+        let saved = film();
+        save(&path, &saved, 42).unwrap();
+        let loaded = load(&path, 42).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((loaded.width, loaded.height), (3, 2));
+        assert_eq!(loaded.passes, 7);
+        for index in 0..saved.pixels.len() {
+            assert_eq!(loaded.pixels[index].e, saved.pixels[index].e);
+        }
+        assert_eq!(loaded.weights, saved.weights);
+        assert_eq!(loaded.luminance, saved.luminance);
+        assert_eq!(loaded.squares, saved.squares);
+        assert_eq!(loaded.samples, saved.samples);
+    }
+
+    #[test]
+    fn rejects_another_fingerprint() {
+        let path = temp_path("mismatch");
+        save(&path, &film(), 42).unwrap();
+        let result = load(&path, 43);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(CheckpointError::Mismatch(_))));
+    }
+
+    #[test]
+    fn rejects_truncated_and_padded_files() {
+        let path = temp_path("truncated");
+        save(&path, &film(), 42).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+
+        std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        let truncated = load(&path, 42);
+        let mut padded = bytes.clone();
+        padded.push(0);
+        std::fs::write(&path, &padded).unwrap();
+        let trailing = load(&path, 42);
+        std::fs::write(&path, b"P6\n").unwrap();
+        let foreign = load(&path, 42);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            truncated,
+            Err(CheckpointError::Invalid(_, "truncated pixel data"))
+        ));
+        assert!(matches!(
+            trailing,
+            Err(CheckpointError::Invalid(_, "trailing data"))
+        ));
+        assert!(matches!(
+            foreign,
+            Err(CheckpointError::Invalid(_, "unknown file type"))
+        ));
+    }
+}
@@ -0,0 +1,428 @@
+use crate::bvh::SplitMethod;
+use crate::film::{Filter, FilterKind};
+use crate::sampler::SamplerKind;
+use crate::tonemap::{ToneMapper, ToneMapping};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const USAGE: &str = "\
+Usage: raytracer [OPTIONS]
+
+Options:
+  -w, --width <PIXELS>      Image width [default: 3840]
+      --height <PIXELS>     Image height [default: width / aspect ratio]
+      --aspect <RATIO>      Aspect ratio, as `16:9` or `1.777` [default: 16:9]
+  -s, --spp <N>             Samples per pixel, on average with --adaptive [default: 3200]
+      --pass-spp <N>        Render in passes of this many samples per pixel, writing
+                            the image after each [default: all at once, 16 with
+                            --time-limit, --checkpoint or --adaptive]
+      --time-limit <SECS>   Stop after the pass that runs past this many seconds
+      --checkpoint <PATH>   Save the render after every pass, and resume it from
+                            there if the file exists
+      --adaptive <ERROR>    Stop sampling a pixel once the standard error of its mean
+                            is below this fraction of it, e.g. 0.02, and spend the
+                            samples it saves on the noisier pixels
+      --min-spp <N>         Samples every pixel takes before --adaptive can stop it
+                            [default: 16]
+      --max-spp <N>         The most samples --adaptive gives a single pixel
+                            [default: 4 times --spp]
+      --spp-image <PATH>    Also write the samples spent on every pixel, white for the
+                            most a pixel can take
+      --sampler <NAME>      Sample generator: random, stratified, halton, sobol
+                            [default: sobol]
+      --filter <NAME>       Pixel reconstruction filter: box, tent, gaussian, mitchell,
+                            blackman-harris [default: box]
+      --filter-radius <PIXELS>
+                            Radius of the filter [default: 0.5 for box, 1 for tent,
+                            1.5 for gaussian, 2 for mitchell and blackman-harris]
+  -d, --max-depth <N>       Maximum ray bounce depth [default: 50]
+      --roulette-depth <N>  Bounces before Russian roulette may end a path that carries
+                            little light [default: 5]
+      --clamp <L>           Scale every sample down to at most this luminance, which
+                            removes fireflies but darkens [default: no clamping]
+      --clamp-indirect <L>  The same for the light a sample got from more than one
+                            bounce only, leaving direct light alone [default: no clamping]
+  -j, --threads <N>         Number of worker threads [default: 16]
+      --tile-size <PIXELS>  Edge length of the square tiles handed to workers [default: 16]
+  -o, --output <PATH>       Output image path [default: output/final.jpg]
+      --format <FORMAT>     Output format: jpg, png, hdr, pfm [default: from the output extension]
+      --quality <1-100>     JPEG quality [default: 100]
+      --tonemap <OPERATOR>  Tone mapper for jpg/png output: clamp, reinhard,
+                            reinhard-extended, aces, uncharted2 [default: clamp]
+      --exposure <STOPS>    Exposure adjustment applied before tone mapping [default: 0]
+      --white-point <L>     Luminance mapped to white by reinhard-extended
+                            [default: the brightest pixel]
+      --seed <N>            Seed for all random sampling [default: 0]
+      --no-light-sampling   Only find lights by scattering into them, without
+                            sampling them directly
+      --bvh <METHOD>        BVH split method: sah, median (random axis) [default: sah]
+      --bvh-stats           Print the depth, leaf sizes and SAH cost of each BVH built
+      --scene <NAME>        Built-in scene to render [default: test_city]
+      --scene-file <PATH>   Render a JSON scene description instead of a built-in scene
+  -h, --help                Print this help and exit";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Jpeg,
+    Png,
+    // Floating point formats, written without any clamping or gamma.
+    Hdr,
+    Pfm,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "jpg" | "jpeg" => Some(Self::Jpeg),
+            "png" => Some(Self::Png),
+            "hdr" => Some(Self::Hdr),
+            "pfm" => Some(Self::Pfm),
+            _ => None,
+        }
+    }
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(Self::from_name)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub image_width: u32,
+    pub image_height: u32,
+    pub aspect_ratio: f64,
+    pub samples_per_pixel: u32,
+    pub pass_samples: u32,
+    pub time_limit: Option<Duration>,
+    pub checkpoint: Option<PathBuf>,
+    pub min_samples: u32,
+    // The most samples a pixel takes: `samples_per_pixel`, or more with
+    // adaptive sampling, where that is a mean.
+    pub max_samples: u32,
+    pub max_error: Option<f64>,
+    pub spp_image: Option<(PathBuf, OutputFormat)>,
+    pub sampler: SamplerKind,
+    pub filter: Filter,
+    pub max_depth: i32,
+    pub roulette_depth: i32,
+    pub clamp: Option<f64>,
+    pub clamp_indirect: Option<f64>,
+    pub thread_num: u32,
+    pub tile_size: u32,
+    pub seed: u64,
+    pub output: PathBuf,
+    pub format: OutputFormat,
+    pub quality: u8,
+    pub tone_mapping: ToneMapping,
+    pub scene: String,
+    pub scene_file: Option<PathBuf>,
+    pub light_sampling: bool,
+    pub bvh_split: SplitMethod,
+    pub bvh_stats: bool,
+}
+
+pub enum Command {
+    Render(Box<Options>),
+    Help,
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse::<T>()
+        .map_err(|_| format!("invalid value `{}` for `{}`", value, flag))
+}
+
+fn parse_positive(flag: &str, value: &str) -> Result<u32, String> {
+    let n: u32 = parse_number(flag, value)?;
+    if n == 0 {
+        return Err(format!("`{}` must be greater than zero", flag));
+    }
+    Ok(n)
+}
+
+fn parse_aspect(value: &str) -> Result<f64, String> {
+    let ratio = match value.split_once(':') {
+        Some((w, h)) => {
+            let w: f64 = parse_number("--aspect", w.trim())?;
+            let h: f64 = parse_number("--aspect", h.trim())?;
+            w / h
+        }
+        None => parse_number("--aspect", value)?,
+    };
+    if !ratio.is_finite() || ratio <= 0.0 {
+        return Err(format!("invalid value `{}` for `--aspect`", value));
+    }
+    Ok(ratio)
+}
+
+pub fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut width: u32 = 3840;
+    let mut height: Option<u32> = None;
+    let mut aspect_ratio: f64 = 16.0 / 9.0;
+    let mut samples_per_pixel: u32 = 16 * 200;
+    let mut pass_samples: Option<u32> = None;
+    let mut time_limit: Option<Duration> = None;
+    let mut checkpoint: Option<PathBuf> = None;
+    let mut min_samples: Option<u32> = None;
+    let mut max_samples: Option<u32> = None;
+    let mut max_error: Option<f64> = None;
+    let mut spp_image: Option<PathBuf> = None;
+    let mut sampler = SamplerKind::Sobol;
+    let mut filter = FilterKind::Box;
+    let mut filter_radius: Option<f64> = None;
+    let mut max_depth: u32 = 50;
+    let mut roulette_depth: u32 = 5;
+    let mut clamp: Option<f64> = None;
+    let mut clamp_indirect: Option<f64> = None;
+    let mut thread_num: u32 = 16;
+    let mut tile_size: u32 = 16;
+    let mut seed: u64 = 0;
+    let mut output = PathBuf::from("output/final.jpg");
+    let mut format: Option<OutputFormat> = None;
+    let mut quality: u32 = 100;
+    let mut tone_mapping = ToneMapping {
+        operator: ToneMapper::Clamp,
+        exposure: 0.0,
+        white_point: None,
+    };
+    let mut scene = String::from("test_city");
+    let mut scene_file: Option<PathBuf> = None;
+    let mut light_sampling = true;
+    let mut bvh_split = SplitMethod::Sah;
+    let mut bvh_stats = false;
+
+    let mut args = args.peekable();
+    while let Some(arg) = args.next() {
+        // Accept both `--flag value` and `--flag=value`.
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value)),
+            _ => (arg.clone(), None),
+        };
+        if flag == "-h" || flag == "--help" {
+            return Ok(Command::Help);
+        }
+        if flag == "--no-light-sampling" {
+            light_sampling = false;
+            continue;
+        }
+        if flag == "--bvh-stats" {
+            bvh_stats = true;
+            continue;
+        }
+        let value = match inline_value {
+            Some(value) => value.to_string(),
+            None => args
+                .next()
+                .ok_or_else(|| format!("missing value for `{}`", flag))?,
+        };
+        match flag.as_str() {
+            "-w" | "--width" => width = parse_positive(&flag, &value)?,
+            "--height" => height = Some(parse_positive(&flag, &value)?),
+            "--aspect" => aspect_ratio = parse_aspect(&value)?,
+            "-s" | "--spp" => samples_per_pixel = parse_positive(&flag, &value)?,
+            "--pass-spp" => pass_samples = Some(parse_positive(&flag, &value)?),
+            "--time-limit" => {
+                let seconds: f64 = parse_number(&flag, &value)?;
+                if !seconds.is_finite() || seconds <= 0.0 {
+                    return Err(format!("`{}` must be a positive number", flag));
+                }
+                time_limit = Some(Duration::from_secs_f64(seconds));
+            }
+            "--checkpoint" => checkpoint = Some(PathBuf::from(value)),
+            "--adaptive" => {
+                let error: f64 = parse_number(&flag, &value)?;
+                if !error.is_finite() || error <= 0.0 {
+                    return Err(format!("`{}` must be a positive number", flag));
+                }
+                max_error = Some(error);
+            }
+            "--min-spp" => min_samples = Some(parse_positive(&flag, &value)?),
+            "--max-spp" => max_samples = Some(parse_positive(&flag, &value)?),
+            "--spp-image" => spp_image = Some(PathBuf::from(value)),
+            "--sampler" => {
+                sampler = SamplerKind::from_name(&value).ok_or_else(|| {
+                    format!(
+                        "unknown sampler `{}`, expected one of: {}",
+                        value,
+                        SamplerKind::NAMES.join(", ")
+                    )
+                })?
+            }
+            "--filter" => {
+                filter = FilterKind::from_name(&value).ok_or_else(|| {
+                    format!(
+                        "unknown filter `{}`, expected one of: {}",
+                        value,
+                        FilterKind::NAMES.join(", ")
+                    )
+                })?
+            }
+            "--filter-radius" => {
+                let radius: f64 = parse_number(&flag, &value)?;
+                if !radius.is_finite() || radius <= 0.0 {
+                    return Err(format!("`{}` must be a positive number", flag));
+                }
+                filter_radius = Some(radius);
+            }
+            "-d" | "--max-depth" => max_depth = parse_positive(&flag, &value)?,
+            "--roulette-depth" => roulette_depth = parse_number(&flag, &value)?,
+            "--clamp" | "--clamp-indirect" => {
+                let max: f64 = parse_number(&flag, &value)?;
+                if !max.is_finite() || max <= 0.0 {
+                    return Err(format!("`{}` must be a positive number", flag));
+                }
+                if flag == "--clamp" {
+                    clamp = Some(max);
+                } else {
+                    clamp_indirect = Some(max);
+                }
+            }
+            "-j" | "--threads" => thread_num = parse_positive(&flag, &value)?,
+            "--tile-size" => tile_size = parse_positive(&flag, &value)?,
+            "-o" | "--output" => output = PathBuf::from(value),
+            "--format" => {
+                format = Some(
+                    OutputFormat::from_name(&value)
+                        .ok_or_else(|| format!("unknown output format `{}`", value))?,
+                )
+            }
+            "--quality" => {
+                quality = parse_number(&flag, &value)?;
+                if !(1..=100).contains(&quality) {
+                    return Err(String::from("`--quality` must be between 1 and 100"));
+                }
+            }
+            "--seed" => seed = parse_number(&flag, &value)?,
+            "--tonemap" => {
+                tone_mapping.operator = ToneMapper::from_name(&value).ok_or_else(|| {
+                    format!(
+                        "unknown tone mapper `{}`, expected one of: {}",
+                        value,
+                        ToneMapper::NAMES.join(", ")
+                    )
+                })?
+            }
+            "--exposure" => {
+                tone_mapping.exposure = parse_number(&flag, &value)?;
+                if !tone_mapping.exposure.is_finite() {
+                    return Err(format!("invalid value `{}` for `{}`", value, flag));
+                }
+            }
+            "--white-point" => {
+                let white: f64 = parse_number(&flag, &value)?;
+                if !white.is_finite() || white <= 0.0 {
+                    return Err(format!("`{}` must be a positive number", flag));
+                }
+                tone_mapping.white_point = Some(white);
+            }
+            "--bvh" => {
+                bvh_split = SplitMethod::from_name(&value).ok_or_else(|| {
+                    format!(
+                        "unknown BVH split method `{}`, expected one of: {}",
+                        value,
+                        SplitMethod::NAMES.join(", ")
+                    )
+                })?
+            }
+            "--scene" => scene = value,
+            "--scene-file" => scene_file = Some(PathBuf::from(value)),
+            _ => return Err(format!("unknown argument `{}`", flag)),
+        }
+    }
+
+    // An explicit height overrides the aspect ratio.
+    let image_height = match height {
+        Some(h) => {
+            aspect_ratio = width as f64 / h as f64;
+            h
+        }
+        None => ((width as f64 / aspect_ratio) as u32).max(1),
+    };
+    let format = match format {
+        Some(f) => f,
+        None => OutputFormat::from_path(&output).ok_or_else(|| {
+            format!(
+                "cannot infer the output format from `{}`, use `--format`",
+                output.display()
+            )
+        })?,
+    };
+    if max_error.is_none() {
+        for (given, flag) in [
+            (min_samples.is_some(), "--min-spp"),
+            (max_samples.is_some(), "--max-spp"),
+            (spp_image.is_some(), "--spp-image"),
+        ] {
+            if given {
+                return Err(format!("`{}` needs `--adaptive`", flag));
+            }
+        }
+    }
+    let spp_image = match spp_image {
+        Some(path) => {
+            let format = OutputFormat::from_path(&path).ok_or_else(|| {
+                format!(
+                    "cannot infer the format of `--spp-image` from `{}`",
+                    path.display()
+                )
+            })?;
+            Some((path, format))
+        }
+        None => None,
+    };
+    // Without passes the time limit could only be checked, the checkpoint only
+    // saved and converged pixels only found at the very end.
+    let pass_samples = match pass_samples {
+        Some(n) => n.min(samples_per_pixel),
+        None if time_limit.is_some() || checkpoint.is_some() || max_error.is_some() => {
+            samples_per_pixel.min(16)
+        }
+        None => samples_per_pixel,
+    };
+    let max_samples = match (max_error, max_samples) {
+        (None, _) => samples_per_pixel,
+        (Some(_), Some(n)) if n < samples_per_pixel => {
+            return Err(String::from("`--max-spp` must be at least `--spp`"))
+        }
+        (Some(_), Some(n)) => n,
+        (Some(_), None) => samples_per_pixel.saturating_mul(4),
+    };
+    if max_depth > i32::MAX as u32 {
+        return Err(String::from("`--max-depth` is too large"));
+    }
+    // Past the maximum depth roulette would never be reached anyway.
+    let roulette_depth = roulette_depth.min(max_depth);
+
+    Ok(Command::Render(Box::new(Options {
+        image_width: width,
+        image_height,
+        aspect_ratio,
+        samples_per_pixel,
+        pass_samples,
+        time_limit,
+        checkpoint,
+        min_samples: min_samples.unwrap_or(16).min(samples_per_pixel),
+        max_samples,
+        max_error,
+        spp_image,
+        sampler,
+        filter: Filter::construct(filter, filter_radius),
+        max_depth: max_depth as i32,
+        roulette_depth: roulette_depth as i32,
+        clamp,
+        clamp_indirect,
+        thread_num,
+        tile_size,
+        seed,
+        output,
+        format,
+        quality: quality as u8,
+        tone_mapping,
+        scene,
+        scene_file,
+        light_sampling,
+        bvh_split,
+        bvh_stats,
+    })))
+}
@@ -2,7 +2,9 @@ use crate::aabb::Aabb;
 use crate::hittable::{HitRecord, Hittable};
 use crate::material::{Isotropic, Material};
 use crate::ray::Ray;
-use crate::rt_weekend::{random_double, INFINITY};
+use crate::rt_weekend::INFINITY;
+use crate::sampler::sample_1d;
+use crate::texture::Texture;
 use crate::vec3::Color3;
 use std::sync::Arc;
 
@@ -12,13 +14,13 @@ pub struct ConstantMedium {
     pub neg_inv_density: f64,
 }
 impl ConstantMedium {
-    // pub fn construct(b: Arc<dyn Hittable>, d: f64, a: Arc<dyn Texture>) -> Self {
-    //     Self {
-    //         boundary: Arc::clone(&b),
-    //         neg_inv_density: -1.0 / d,
-    //         phase_function: Arc::new(Isotropic::construct(a)),
-    //     }
-    // }
+    pub fn construct(b: Arc<dyn Hittable>, d: f64, a: Arc<dyn Texture>) -> Self {
+        Self {
+            boundary: Arc::clone(&b),
+            neg_inv_density: -1.0 / d,
+            phase_function: Arc::new(Isotropic::construct(a)),
+        }
+    }
     pub fn construct_color(b: Arc<dyn Hittable>, d: f64, c: &Color3) -> Self {
         Self {
             boundary: Arc::clone(&b),
@@ -54,7 +56,7 @@ impl Hittable for ConstantMedium {
 
         let ray_length = r.direction().length();
         let distance_inside_boundary = (rec2.t - rec1.t) * ray_length;
-        let hit_distance = self.neg_inv_density * random_double().ln();
+        let hit_distance = self.neg_inv_density * (1.0 - sample_1d()).ln();
 
         if hit_distance > distance_inside_boundary {
             return false;
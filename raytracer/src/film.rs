@@ -0,0 +1,318 @@
+// Turns samples into pixels through a reconstruction filter. A sample counts
+// towards every pixel whose centre is within the filter radius of it, weighted
+// by the filter, and a pixel is the weighted mean of the samples it got.
+use crate::render::Tile;
+use crate::rt_weekend::PI;
+use crate::vec3::{luminance, Color3};
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterKind {
+    // Each sample counts for its own pixel only.
+    Box,
+    Tent,
+    Gaussian,
+    // Mitchell-Netravali with B = C = 1/3. Its negative lobes sharpen.
+    Mitchell,
+    BlackmanHarris,
+}
+
+impl FilterKind {
+    pub const NAMES: [&'static str; 5] = ["box", "tent", "gaussian", "mitchell", "blackman-harris"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "box" => Some(Self::Box),
+            "tent" => Some(Self::Tent),
+            "gaussian" => Some(Self::Gaussian),
+            "mitchell" => Some(Self::Mitchell),
+            "blackman-harris" => Some(Self::BlackmanHarris),
+            _ => None,
+        }
+    }
+    // In pixels.
+    pub fn default_radius(self) -> f64 {
+        match self {
+            Self::Box => 0.5,
+            Self::Tent => 1.0,
+            Self::Gaussian => 1.5,
+            Self::Mitchell | Self::BlackmanHarris => 2.0,
+        }
+    }
+}
+
+impl fmt::Display for FilterKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", Self::NAMES[*self as usize])
+    }
+}
+
+// A separable filter: the product of the same profile along x and y.
+#[derive(Clone, Copy, Debug)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub radius: f64,
+}
+
+impl Filter {
+    pub fn construct(kind: FilterKind, radius: Option<f64>) -> Self {
+        Self {
+            kind,
+            radius: radius.unwrap_or_else(|| kind.default_radius()),
+        }
+    }
+    // The weight of a sample at an offset of `(dx, dy)` pixels from a pixel
+    // centre. Only the ratios of weights matter.
+    pub fn evaluate(&self, dx: f64, dy: f64) -> f64 {
+        self.profile(dx) * self.profile(dy)
+    }
+    fn profile(&self, x: f64) -> f64 {
+        let r = self.radius;
+        // Half open, so that with a box of radius 1/2 every sample lands in
+        // exactly one pixel.
+        if x < -r || x >= r {
+            return 0.0;
+        }
+        let x = x.abs();
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => r - x,
+            FilterKind::Gaussian => {
+                // Shifted down to reach zero at the radius.
+                let sigma = r / 3.0;
+                let gaussian = |x: f64| (-x * x / (2.0 * sigma * sigma)).exp();
+                gaussian(x) - gaussian(r)
+            }
+            FilterKind::Mitchell => {
+                const B: f64 = 1.0 / 3.0;
+                const C: f64 = 1.0 / 3.0;
+                let t = 2.0 * x / r;
+                if t < 1.0 {
+                    ((12.0 - 9.0 * B - 6.0 * C) * t * t * t
+                        + (-18.0 + 12.0 * B + 6.0 * C) * t * t
+                        + (6.0 - 2.0 * B))
+                        / 6.0
+                } else {
+                    ((-B - 6.0 * C) * t * t * t
+                        + (6.0 * B + 30.0 * C) * t * t
+                        + (-12.0 * B - 48.0 * C) * t
+                        + (8.0 * B + 24.0 * C))
+                        / 6.0
+                }
+            }
+            FilterKind::BlackmanHarris => {
+                // The window over [-r, r], at its peak in the middle.
+                let t = 2.0 * PI * (0.5 + x / (2.0 * r));
+                0.35875 - 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() - 0.01168 * (3.0 * t).cos()
+            }
+        }
+    }
+    // How many pixels beyond its own a sample can reach.
+    fn margin(&self) -> u32 {
+        (self.radius + 0.5).ceil() as u32
+    }
+}
+
+// Filter-weighted sums of the radiance of the samples around every pixel, row
+// by row from the top, and the sums of their weights. Adaptive sampling also
+// needs the samples taken in each pixel: the sum and the sum of squares of
+// their luminance, and their number. The samples were taken in `passes`
+// passes.
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Color3>,
+    pub weights: Vec<f64>,
+    pub luminance: Vec<f64>,
+    pub squares: Vec<f64>,
+    pub samples: Vec<u32>,
+    pub passes: u32,
+}
+
+impl Film {
+    // Luminance that the error of darker pixels is measured against, or the
+    // darkest ones would never converge.
+    const DARK: f64 = 0.01;
+
+    pub fn new(width: u32, height: u32) -> Self {
+        let len = (width * height) as usize;
+        Self {
+            width,
+            height,
+            pixels: vec![Color3::new(); len],
+            weights: vec![0.0; len],
+            luminance: vec![0.0; len],
+            squares: vec![0.0; len],
+            samples: vec![0; len],
+            passes: 0,
+        }
+    }
+    // The filtered radiance of every pixel.
+    pub fn average(&self) -> Vec<Color3> {
+        self.pixels
+            .iter()
+            .zip(&self.weights)
+            .map(|(c, w)| if *w > 0.0 { *c / *w } else { Color3::new() })
+            .collect()
+    }
+    pub fn mean_samples(&self) -> f64 {
+        let total: u64 = self.samples.iter().map(|n| *n as u64).sum();
+        total as f64 / self.samples.len() as f64
+    }
+    // The standard error of the mean luminance of the samples taken in a
+    // pixel, relative to that mean.
+    pub fn relative_error(&self, index: usize) -> f64 {
+        let n = self.samples[index] as f64;
+        if n < 2.0 {
+            return f64::INFINITY;
+        }
+        let mean = self.luminance[index] / n;
+        let variance = ((self.squares[index] - n * mean * mean) / (n - 1.0)).max(0.0);
+        (variance / n).sqrt() / mean.max(Self::DARK)
+    }
+    // Tiles overlap where their samples reach, so to get the same sums every
+    // time they must be merged in the same order.
+    pub fn merge(&mut self, tile: TileFilm) {
+        let bounds = &tile.bounds;
+        let mut index = 0;
+        for y in bounds.y0..bounds.y1 {
+            for x in bounds.x0..bounds.x1 {
+                let pixel = (y * self.width + x) as usize;
+                self.pixels[pixel] += tile.pixels[index];
+                self.weights[pixel] += tile.weights[index];
+                index += 1;
+            }
+        }
+        let mut index = 0;
+        for y in tile.tile.y0..tile.tile.y1 {
+            for x in tile.tile.x0..tile.tile.x1 {
+                let pixel = (y * self.width + x) as usize;
+                self.luminance[pixel] += tile.luminance[index];
+                self.squares[pixel] += tile.squares[index];
+                self.samples[pixel] += tile.samples[index];
+                index += 1;
+            }
+        }
+    }
+}
+
+// The samples of one tile, to be merged into the film.
+pub struct TileFilm {
+    tile: Tile,
+    // The pixels the samples are splatted on: the tile grown by the filter
+    // margin, within the image.
+    bounds: Tile,
+    filter: Filter,
+    pixels: Vec<Color3>,
+    weights: Vec<f64>,
+    luminance: Vec<f64>,
+    squares: Vec<f64>,
+    samples: Vec<u32>,
+}
+
+impl TileFilm {
+    // An empty film for the samples of `tile`, covering the pixels of the
+    // `width` by `height` image that they reach.
+    pub fn construct(tile: &Tile, filter: Filter, width: u32, height: u32) -> Self {
+        let margin = filter.margin();
+        let x0 = tile.x0.saturating_sub(margin);
+        let y0 = tile.y0.saturating_sub(margin);
+        let x1 = (tile.x1 + margin).min(width);
+        let y1 = (tile.y1 + margin).min(height);
+        let splat_len = ((x1 - x0) * (y1 - y0)) as usize;
+        let len = ((tile.x1 - tile.x0) * (tile.y1 - tile.y0)) as usize;
+        Self {
+            tile: *tile,
+            bounds: Tile { x0, y0, x1, y1 },
+            filter,
+            pixels: vec![Color3::new(); splat_len],
+            weights: vec![0.0; splat_len],
+            luminance: vec![0.0; len],
+            squares: vec![0.0; len],
+            samples: vec![0; len],
+        }
+    }
+    fn index(&self, x: u32, y: u32) -> usize {
+        ((y - self.tile.y0) * (self.tile.x1 - self.tile.x0) + x - self.tile.x0) as usize
+    }
+    // A sample taken in pixel `(x, y)` of the tile, at `(px, py)` in pixels
+    // from the top left corner of the image.
+    pub fn add_sample(&mut self, x: u32, y: u32, px: f64, py: f64, color: &Color3) {
+        let index = self.index(x, y);
+        let l = luminance(color);
+        self.luminance[index] += l;
+        self.squares[index] += l * l;
+        self.samples[index] += 1;
+
+        let r = self.filter.radius;
+        let b = &self.bounds;
+        let sx0 = ((px - r - 0.5).floor().max(b.x0 as f64)) as u32;
+        let sy0 = ((py - r - 0.5).floor().max(b.y0 as f64)) as u32;
+        let sx1 = ((px + r + 0.5).ceil() as u32).min(b.x1);
+        let sy1 = ((py + r + 0.5).ceil() as u32).min(b.y1);
+        let width = b.x1 - b.x0;
+        for sy in sy0..sy1 {
+            let dy = py - (sy as f64 + 0.5);
+            for sx in sx0..sx1 {
+                let weight = self.filter.evaluate(px - (sx as f64 + 0.5), dy);
+                if weight == 0.0 {
+                    continue;
+                }
+                let splat = ((sy - b.y0) * width + sx - b.x0) as usize;
+                self.pixels[splat] += weight * *color;
+                self.weights[splat] += weight;
+            }
+        }
+    }
+    // A sample taken in pixel `(x, y)` that is left out. It adds no light, but
+    // counts as taken so that the next pass goes on from the sample after it.
+    pub fn skip_sample(&mut self, x: u32, y: u32) {
+        let index = self.index(x, y);
+        self.samples[index] += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn box_filter_puts_each_sample_in_one_pixel() {
+        let filter = Filter::construct(FilterKind::Box, None);
+        let tile = Tile {
+            x0: 0,
+            y0: 0,
+            x1: 4,
+            y1: 4,
+        };
+        let mut film = Film::new(4, 4);
+        let mut tile_film = TileFilm::construct(&tile, filter, 4, 4);
+        let mut count = 0;
+        for y in 0..4 {
+            for x in 0..4 {
+                // The corners and edges of the pixel too.
+                for (fx, fy) in [
+                    (0.0, 0.0),
+                    (0.5, 0.5),
+                    (0.0, 0.75),
+                    (0.999, 0.25),
+                    (0.3, 0.0),
+                ] {
+                    tile_film.add_sample(
+                        x,
+                        y,
+                        x as f64 + fx,
+                        y as f64 + fy,
+                        &Color3::construct(&[1.0, 1.0, 1.0]),
+                    );
+                    count += 1;
+                }
+            }
+        }
+        film.merge(tile_film);
+        for weight in &film.weights {
+            assert_eq!(*weight, 5.0);
+        }
+        assert_eq!(film.weights.iter().sum::<f64>(), count as f64);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::aabb::Aabb;
+use crate::hittable_list::HittableList;
 use crate::material::Material;
 use crate::ray::Ray;
 use crate::rt_weekend::{degrees_to_radians, INFINITY};
@@ -48,8 +49,41 @@ impl HitRecord {
 }
 
 pub trait Hittable: Send + Sync {
+    // Finds the closest hit in (t_min, t_max). `rec` is only written when
+    // something is hit.
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool;
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut Aabb) -> bool;
+    // Whether anything at all is hit in (t_min, t_max), for shadow rays. Stops
+    // at the first hit found and fills no `HitRecord`.
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        let mut rec = HitRecord::new();
+        self.hit(r, t_min, t_max, &mut rec)
+    }
+
+    // Light sampling. `random` returns a direction from `o` towards a random
+    // point of the object, and `pdf_value` the density over solid angle with
+    // which `random` picks the direction `v`.
+    fn pdf_value(&self, _o: &Point3, _v: &Vec3) -> f64 {
+        0.0
+    }
+    fn random(&self, _o: &Point3) -> Vec3 {
+        Vec3::construct(&[1.0, 0.0, 0.0])
+    }
+    // An emissive object that implements `pdf_value` and `random`.
+    fn is_light(&self) -> bool {
+        false
+    }
+    // Adds the lights inside a composite object to `lights`.
+    fn collect_lights(&self, _lights: &mut HittableList) {}
+}
+
+// Adds `object` to `lights` if it is a light itself, otherwise its parts that are.
+pub fn collect_lights_of(object: &Arc<dyn Hittable>, lights: &mut HittableList) {
+    if object.is_light() {
+        lights.add(Arc::clone(object));
+    } else {
+        object.collect_lights(lights);
+    }
 }
 
 pub struct Translate {
@@ -73,6 +107,10 @@ impl Hittable for Translate {
         rec.p += self.offset;
         true
     }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        let moved_r = Ray::construct(&(r.origin() - self.offset), &r.direction(), r.time());
+        self.ptr.occluded(&moved_r, t_min, t_max)
+    }
     fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
         if !self.ptr.bounding_box(_time0, _time1, output_box) {
             return false;
@@ -84,72 +122,24 @@ impl Hittable for Translate {
         println!("bbox: {:?}", *output_box);
         true
     }
-}
-
-pub struct RotateX {
-    pub ptr: Arc<dyn Hittable>,
-    pub sin_theta: f64,
-    pub cos_theta: f64,
-    pub hasbox: bool,
-    pub bbox: Aabb,
-}
-impl RotateX {
-    // pub fn construct(p: Arc<dyn Hittable>, angle: f64) -> Self {
-    //     let radians = angle.to_radians();
-    //     let sin_theta = radians.sin();
-    //     let cos_theta = radians.cos();
-    //     let mut bbox = Aabb::new();
-    //     let hasbox = p.bounding_box(0.0, 1.0, &mut bbox);
-    //     println!("bbox: {:?}", bbox);
-    //     let mut mini = Point3::construct(&[INFINITY, INFINITY, INFINITY]);
-    //     let mut maxi = Point3::construct(&[-INFINITY, -INFINITY, -INFINITY]);
-
-    //     for i in 0..2 {
-    //         for j in 0..2 {
-    //             for k in 0..2 {
-    //                 let x = i as f64 * bbox.maximum().x() + (1.0 - i as f64) * bbox.minimum().x();
-    //                 let y = j as f64 * bbox.maximum().y() + (1.0 - j as f64) * bbox.minimum().y();
-    //                 let z = k as f64 * bbox.maximum().z() + (1.0 - k as f64) * bbox.minimum().z();
-
-    //                 let pre_rot = Vec3::construct(&[x, y, z]);
-    //                 let tester = rotate_vec_x(&pre_rot, -sin_theta, cos_theta);
-    //                 for c in 0..3 {
-    //                     mini.e[c] = tester.e[c].min(mini.e[c]);
-    //                     maxi.e[c] = tester.e[c].max(maxi.e[c]);
-    //                 }
-    //             }
-    //         }
-    //     }
-    //     bbox = Aabb::construct(&mini, &maxi);
-    //     Self {
-    //         ptr: Arc::clone(&p),
-    //         sin_theta,
-    //         cos_theta,
-    //         hasbox,
-    //         bbox,
-    //     }
-    // }
-}
-impl Hittable for RotateX {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
-        let origin = rotate_vec_x(&r.origin(), self.sin_theta, self.cos_theta);
-        let direction = rotate_vec_x(&r.direction(), self.sin_theta, self.cos_theta);
-        let rotated_r = Ray::construct(&origin, &direction, r.time());
-
-        if !self.ptr.hit(&rotated_r, t_min, t_max, rec) {
-            return false;
-        }
-
-        let p = rotate_vec_x(&rec.p, -self.sin_theta, self.cos_theta);
-        let normal = rotate_vec_x(&rec.normal, -self.sin_theta, self.cos_theta);
-        rec.p = p;
-        rec.normal = normal;
-
-        true
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        self.ptr.pdf_value(&(*o - self.offset), v)
     }
-    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
-        *output_box = self.bbox;
-        self.hasbox
+    fn random(&self, o: &Point3) -> Vec3 {
+        self.ptr.random(&(*o - self.offset))
+    }
+    fn is_light(&self) -> bool {
+        self.ptr.is_light()
+    }
+    fn collect_lights(&self, lights: &mut HittableList) {
+        let mut inner = HittableList::new();
+        self.ptr.collect_lights(&mut inner);
+        for light in inner.objects {
+            lights.add(Arc::new(Self {
+                ptr: light,
+                offset: self.offset,
+            }));
+        }
     }
 }
 
@@ -217,76 +207,41 @@ impl Hittable for RotateY {
 
         true
     }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        let origin = rotate_vec_y(&r.origin(), self.sin_theta, self.cos_theta);
+        let direction = rotate_vec_y(&r.direction(), self.sin_theta, self.cos_theta);
+        self.ptr
+            .occluded(&Ray::construct(&origin, &direction, r.time()), t_min, t_max)
+    }
     fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
         *output_box = self.bbox;
         self.hasbox
     }
-}
-pub struct RotateZ {
-    pub ptr: Arc<dyn Hittable>,
-    pub sin_theta: f64,
-    pub cos_theta: f64,
-    pub hasbox: bool,
-    pub bbox: Aabb,
-}
-impl RotateZ {
-    // pub fn construct(p: Arc<dyn Hittable>, angle: f64) -> Self {
-    //     let radians = angle.to_radians();
-    //     let sin_theta = radians.sin();
-    //     let cos_theta = radians.cos();
-    //     let mut bbox = Aabb::new();
-    //     let hasbox = p.bounding_box(0.0, 1.0, &mut bbox);
-    //     println!("bbox: {:?}", bbox);
-    //     let mut mini = Point3::construct(&[INFINITY, INFINITY, INFINITY]);
-    //     let mut maxi = Point3::construct(&[-INFINITY, -INFINITY, -INFINITY]);
-
-    //     for i in 0..2 {
-    //         for j in 0..2 {
-    //             for k in 0..2 {
-    //                 let x = i as f64 * bbox.maximum().x() + (1.0 - i as f64) * bbox.minimum().x();
-    //                 let y = j as f64 * bbox.maximum().y() + (1.0 - j as f64) * bbox.minimum().y();
-    //                 let z = k as f64 * bbox.maximum().z() + (1.0 - k as f64) * bbox.minimum().z();
-
-    //                 let pre_rotate = Vec3::construct(&[x, y, z]);
-    //                 let tester = rotate_vec_z(&pre_rotate, -sin_theta, cos_theta);
-    //                 for c in 0..3 {
-    //                     mini.e[c] = tester.e[c].min(mini.e[c]);
-    //                     maxi.e[c] = tester.e[c].max(maxi.e[c]);
-    //                 }
-    //             }
-    //         }
-    //     }
-    //     bbox = Aabb::construct(&mini, &maxi);
-    //     Self {
-    //         ptr: Arc::clone(&p),
-    //         sin_theta,
-    //         cos_theta,
-    //         hasbox,
-    //         bbox,
-    //     }
-    // }
-}
-impl Hittable for RotateZ {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
-        let origin = rotate_vec_z(&r.origin(), self.sin_theta, self.cos_theta);
-        let direction = rotate_vec_z(&r.direction(), self.sin_theta, self.cos_theta);
-
-        let rotated_r = Ray::construct(&origin, &direction, r.time());
-
-        if !self.ptr.hit(&rotated_r, t_min, t_max, rec) {
-            return false;
-        }
-
-        let p = rotate_vec_z(&rec.p, -self.sin_theta, self.cos_theta);
-        let normal = rotate_vec_z(&rec.normal, -self.sin_theta, self.cos_theta);
-        rec.p = p;
-        rec.normal = normal;
-
-        true
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        let o = rotate_vec_y(o, self.sin_theta, self.cos_theta);
+        let v = rotate_vec_y(v, self.sin_theta, self.cos_theta);
+        self.ptr.pdf_value(&o, &v)
     }
-    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
-        *output_box = self.bbox;
-        self.hasbox
+    fn random(&self, o: &Point3) -> Vec3 {
+        let o = rotate_vec_y(o, self.sin_theta, self.cos_theta);
+        rotate_vec_y(&self.ptr.random(&o), -self.sin_theta, self.cos_theta)
+    }
+    fn is_light(&self) -> bool {
+        self.ptr.is_light()
+    }
+    fn collect_lights(&self, lights: &mut HittableList) {
+        let mut inner = HittableList::new();
+        self.ptr.collect_lights(&mut inner);
+        for light in inner.objects {
+            // Only used for sampling, so the box of the whole object will do.
+            lights.add(Arc::new(Self {
+                ptr: light,
+                sin_theta: self.sin_theta,
+                cos_theta: self.cos_theta,
+                hasbox: self.hasbox,
+                bbox: self.bbox,
+            }));
+        }
     }
 }
 
@@ -297,17 +252,3 @@ fn rotate_vec_y(v: &Vec3, sin_theta: f64, cos_theta: f64) -> Vec3 {
         sin_theta * v.x() + cos_theta * v.z(),
     ])
 }
-fn rotate_vec_x(v: &Vec3, sin_theta: f64, cos_theta: f64) -> Vec3 {
-    Vec3::construct(&[
-        v.x(),
-        cos_theta * v.y() + sin_theta * v.z(),
-        -sin_theta * v.y() + cos_theta * v.z(),
-    ])
-}
-fn rotate_vec_z(v: &Vec3, sin_theta: f64, cos_theta: f64) -> Vec3 {
-    Vec3::construct(&[
-        cos_theta * v.x() + sin_theta * v.y(),
-        -sin_theta * v.x() + cos_theta * v.y(),
-        v.z(),
-    ])
-}
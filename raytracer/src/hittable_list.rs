@@ -1,7 +1,8 @@
-// use crate::vec3::Point3;
 use crate::aabb::Aabb;
 use crate::hittable;
 use crate::ray::Ray;
+use crate::sampler::sample_1d;
+use crate::vec3::{Point3, Vec3};
 
 use hittable::{HitRecord, Hittable};
 use std::sync::Arc;
@@ -48,6 +49,11 @@ impl Hittable for HittableList {
         }
         hit_anything
     }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.objects
+            .iter()
+            .any(|object| object.occluded(r, t_min, t_max))
+    }
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut Aabb) -> bool {
         if self.objects.is_empty() {
             return false;
@@ -69,4 +75,25 @@ impl Hittable for HittableList {
         }
         true
     }
+    // The lights are picked with equal probability.
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+        let weight = 1.0 / self.objects.len() as f64;
+        self.objects
+            .iter()
+            .map(|object| weight * object.pdf_value(o, v))
+            .sum()
+    }
+    fn random(&self, o: &Point3) -> Vec3 {
+        let count = self.objects.len();
+        let index = ((sample_1d() * count as f64) as usize).min(count - 1);
+        self.objects[index].random(o)
+    }
+    fn collect_lights(&self, lights: &mut HittableList) {
+        for object in &self.objects {
+            hittable::collect_lights_of(object, lights);
+        }
+    }
 }
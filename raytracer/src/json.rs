@@ -0,0 +1,380 @@
+// A small JSON reader that remembers where every value came from, so the scene
+// loader can point at the offending line when something is wrong.
+// `//` line comments are accepted as an extension.
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Pos {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for Pos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    // Keys keep their own position and the order they were written in.
+    Object(Vec<(String, Pos, Json)>),
+}
+
+#[derive(Clone, Debug)]
+pub struct Json {
+    pub value: JsonValue,
+    pub pos: Pos,
+}
+
+impl Json {
+    pub fn type_name(&self) -> &'static str {
+        match self.value {
+            JsonValue::Null => "null",
+            JsonValue::Bool(_) => "a boolean",
+            JsonValue::Number(_) => "a number",
+            JsonValue::String(_) => "a string",
+            JsonValue::Array(_) => "an array",
+            JsonValue::Object(_) => "an object",
+        }
+    }
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match &self.value {
+            JsonValue::Object(fields) => fields.iter().find(|f| f.0 == key).map(|f| &f.2),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub pos: Pos,
+    pub message: String,
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    pos: Pos,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, message: String) -> Result<T, ParseError> {
+        Err(ParseError {
+            pos: self.pos,
+            message,
+        })
+    }
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.pos.line += 1;
+            self.pos.col = 1;
+        } else {
+            self.pos.col += 1;
+        }
+        Some(c)
+    }
+    fn expect(&mut self, expected: char) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.bump();
+                Ok(())
+            }
+            Some(c) => self.error(format!("expected `{}`, found `{}`", expected, c)),
+            None => self.error(format!("expected `{}`, found end of file", expected)),
+        }
+    }
+    fn skip_whitespace(&mut self) -> Result<(), ParseError> {
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() {
+                self.bump();
+            } else if c == '/' {
+                self.bump();
+                if self.peek() != Some('/') {
+                    return self.error(String::from("expected `//` comment"));
+                }
+                while !matches!(self.peek(), Some('\n') | None) {
+                    self.bump();
+                }
+            } else {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_value(&mut self) -> Result<Json, ParseError> {
+        self.skip_whitespace()?;
+        let pos = self.pos;
+        let value = match self.peek() {
+            Some('{') => self.parse_object()?,
+            Some('[') => self.parse_array()?,
+            Some('"') => JsonValue::String(self.parse_string()?),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.parse_number()?,
+            Some(c) if c.is_ascii_alphabetic() => {
+                let mut word = String::new();
+                while let Some(c) = self.peek() {
+                    if !c.is_ascii_alphanumeric() {
+                        break;
+                    }
+                    word.push(c);
+                    self.bump();
+                }
+                match word.as_str() {
+                    "true" => JsonValue::Bool(true),
+                    "false" => JsonValue::Bool(false),
+                    "null" => JsonValue::Null,
+                    _ => {
+                        return Err(ParseError {
+                            pos,
+                            message: format!("unexpected `{}`", word),
+                        })
+                    }
+                }
+            }
+            Some(c) => return self.error(format!("unexpected `{}`", c)),
+            None => return self.error(String::from("unexpected end of file")),
+        };
+        Ok(Json { value, pos })
+    }
+
+    fn parse_object(&mut self) -> Result<JsonValue, ParseError> {
+        self.expect('{')?;
+        let mut fields: Vec<(String, Pos, Json)> = Vec::new();
+        self.skip_whitespace()?;
+        if self.peek() == Some('}') {
+            self.bump();
+            return Ok(JsonValue::Object(fields));
+        }
+        loop {
+            self.skip_whitespace()?;
+            let key_pos = self.pos;
+            if self.peek() != Some('"') {
+                return self.error(String::from("expected a quoted key"));
+            }
+            let key = self.parse_string()?;
+            if let Some(prev) = fields.iter().find(|f| f.0 == key) {
+                return Err(ParseError {
+                    pos: key_pos,
+                    message: format!("duplicate key `{}` (first defined at {})", key, prev.1),
+                });
+            }
+            self.skip_whitespace()?;
+            self.expect(':')?;
+            let value = self.parse_value()?;
+            fields.push((key, key_pos, value));
+            self.skip_whitespace()?;
+            match self.peek() {
+                Some(',') => {
+                    self.bump();
+                }
+                Some('}') => {
+                    self.bump();
+                    return Ok(JsonValue::Object(fields));
+                }
+                _ => return self.error(String::from("expected `,` or `}`")),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<JsonValue, ParseError> {
+        self.expect('[')?;
+        let mut items: Vec<Json> = Vec::new();
+        self.skip_whitespace()?;
+        if self.peek() == Some(']') {
+            self.bump();
+            return Ok(JsonValue::Array(items));
+        }
+        loop {
+            items.push(self.parse_value()?);
+            self.skip_whitespace()?;
+            match self.peek() {
+                Some(',') => {
+                    self.bump();
+                }
+                Some(']') => {
+                    self.bump();
+                    return Ok(JsonValue::Array(items));
+                }
+                _ => return self.error(String::from("expected `,` or `]`")),
+            }
+        }
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, ParseError> {
+        let mut code = 0;
+        for _i in 0..4 {
+            match self.peek().and_then(|c| c.to_digit(16)) {
+                Some(d) => {
+                    code = code * 16 + d;
+                    self.bump();
+                }
+                None => return self.error(String::from("invalid `\\u` escape")),
+            }
+        }
+        Ok(code)
+    }
+
+    fn parse_string(&mut self) -> Result<String, ParseError> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            let c = match self.bump() {
+                Some(c) => c,
+                None => return self.error(String::from("unterminated string")),
+            };
+            match c {
+                '"' => return Ok(s),
+                '\n' => return self.error(String::from("unterminated string")),
+                '\\' => {
+                    let escaped = match self.bump() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            let mut code = self.parse_hex4()?;
+                            // Surrogate pair.
+                            if (0xd800..0xdc00).contains(&code) {
+                                self.expect('\\')?;
+                                self.expect('u')?;
+                                let low = self.parse_hex4()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return self.error(String::from("invalid surrogate pair"));
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            match char::from_u32(code) {
+                                Some(c) => c,
+                                None => return self.error(String::from("invalid `\\u` escape")),
+                            }
+                        }
+                        _ => return self.error(String::from("invalid escape sequence")),
+                    };
+                    s.push(escaped);
+                }
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<JsonValue, ParseError> {
+        let start = self.pos;
+        let mut text = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E') {
+                text.push(c);
+                self.bump();
+            } else {
+                break;
+            }
+        }
+        match text.parse::<f64>() {
+            Ok(x) if x.is_finite() => Ok(JsonValue::Number(x)),
+            _ => Err(ParseError {
+                pos: start,
+                message: format!("invalid number `{}`", text),
+            }),
+        }
+    }
+}
+
+pub fn parse(src: &str) -> Result<Json, ParseError> {
+    let mut parser = Parser {
+        chars: src.chars().peekable(),
+        pos: Pos { line: 1, col: 1 },
+    };
+    let value = parser.parse_value()?;
+    parser.skip_whitespace()?;
+    if let Some(c) = parser.peek() {
+        return parser.error(format!("unexpected `{}` after the end of the document", c));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_at(src: &str) -> (usize, usize, String) {
+        let e = parse(src).unwrap_err();
+        (e.pos.line, e.pos.col, e.message)
+    }
+
+    #[test]
+    fn parses_values_with_positions() {
+        let json = parse(
+            "{\n  // comment\n  \"a\": [1, -2.5e1, true, null],\n  \"b\": \"x\\u00e9\\n\"\n}",
+        )
+        .unwrap();
+        let a = json.get("a").unwrap();
+        assert_eq!(a.pos, Pos { line: 3, col: 8 });
+        match &a.value {
+            JsonValue::Array(items) => {
+                assert_eq!(items.len(), 4);
+                assert!(matches!(items[1].value, JsonValue::Number(x) if x == -25.0));
+                assert!(matches!(items[2].value, JsonValue::Bool(true)));
+                assert!(matches!(items[3].value, JsonValue::Null));
+            }
+            _ => panic!("expected an array"),
+        }
+        assert!(matches!(&json.get("b").unwrap().value, JsonValue::String(s) if s == "xé\n"));
+        assert!(json.get("c").is_none());
+    }
+
+    #[test]
+    fn reports_where_errors_are() {
+        assert_eq!(
+            error_at("{\n  \"a\": 1\n  \"b\": 2\n}"),
+            (3, 3, String::from("expected `,` or `}`"))
+        );
+        assert_eq!(
+            error_at("[1,\n  tru]"),
+            (2, 3, String::from("unexpected `tru`"))
+        );
+        assert_eq!(
+            error_at("{\"a\": 1, \"a\": 2}"),
+            (
+                1,
+                10,
+                String::from("duplicate key `a` (first defined at 1:2)")
+            )
+        );
+        assert_eq!(
+            error_at("[1, 2"),
+            (1, 6, String::from("expected `,` or `]`"))
+        );
+        assert_eq!(
+            error_at("\"abc"),
+            (1, 5, String::from("unterminated string"))
+        );
+        assert_eq!(
+            error_at("  1.2.3"),
+            (1, 3, String::from("invalid number `1.2.3`"))
+        );
+        assert_eq!(
+            error_at("{} /"),
+            (1, 5, String::from("expected `//` comment"))
+        );
+        assert_eq!(
+            error_at("{}\n\n  x"),
+            (
+                3,
+                3,
+                String::from("unexpected `x` after the end of the document")
+            )
+        );
+    }
+}
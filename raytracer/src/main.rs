@@ -1,27 +1,32 @@
-use std::sync::mpsc;
+use std::path::PathBuf;
+use std::process::exit;
 use std::sync::Arc;
-use std::thread;
-use std::{fs::File, process::exit};
 
 use console::style;
-use image::{ImageBuffer, RgbImage};
 use indicatif::ProgressBar;
 
 use aarect::{XyRect, XzRect, YzRect};
 use boxes::Box_;
 use bvh::BVHNode;
 use camera::Camera;
+use checkpoint::fingerprint;
+use cli::{Command, USAGE};
 use constant_medium::ConstantMedium;
-use hittable::{HitRecord, Hittable, RotateY, Translate};
+use film::Film;
+use hittable::{Hittable, RotateY, Translate};
 use hittable_list::HittableList;
 use material::DiffuseLight;
 use material::{Dielectric, Lambertian, Material, Metal};
 use moving_sphere::MovingSphere;
-use obj_loader::load_new;
+use obj_loader::{load_obj, LoadError, ObjOptions};
+use output::save_image;
 use ray::Ray;
-use rt_weekend::{random_double, random_double_range, INFINITY};
+use render::{render, RenderScene, RenderSettings, SampleCounts};
+use rt_weekend::{random_double_range, seed_rng, INFINITY};
+use scene::Scene;
 use sphere::Sphere;
 use texture::{CheckerTexture, ImageTexture, NoiseTexture, Texture};
+use tonemap::{ToneMapper, ToneMapping};
 use vec3::{Color3, Point3, Vec3};
 
 mod aabb;
@@ -29,18 +34,32 @@ mod aarect;
 mod boxes;
 mod bvh;
 mod camera;
+mod checkpoint;
+mod cli;
 mod constant_medium;
+mod film;
 mod hittable;
 mod hittable_list;
+mod json;
 mod material;
 mod moving_sphere;
 mod obj_loader;
+mod onb;
+mod output;
+mod pdf;
 mod perlin;
 mod ray;
+mod render;
 mod rt_weekend;
+mod sampler;
+mod scene;
+mod scene_file;
 mod sphere;
 mod texture;
+mod tonemap;
+mod transform;
 mod triangle;
+mod triangle_mesh;
 mod vec3;
 
 pub fn hit_sphere(center: &Point3, radius: &f64, r: &Ray) -> f64 {
@@ -56,49 +75,6 @@ pub fn hit_sphere(center: &Point3, radius: &f64, r: &Ray) -> f64 {
     }
 }
 
-pub fn ray_color(r: &Ray, background: &Color3, world: &dyn Hittable, depth: i32) -> Color3 {
-    let mut rec: HitRecord = HitRecord::new();
-    if depth <= 0 {
-        return Color3::new();
-    }
-    if !world.hit(r, 0.001, f64::INFINITY, &mut rec) {
-        return *background;
-    }
-
-    let mut scattered: Ray = Ray::new();
-    let mut attenuation: Color3 = Color3::new();
-    let emitted = rec.mat_ptr.as_ref().unwrap().emitted(rec.u, rec.v, &rec.p);
-
-    if !rec
-        .mat_ptr
-        .as_ref()
-        .unwrap()
-        .scatter(r, &rec, &mut attenuation, &mut scattered)
-    {
-        return emitted;
-    }
-    emitted + attenuation * ray_color(&scattered, background, world, depth - 1)
-}
-
-pub fn write_color(pixel_color: &Color3, samples_per_pixel: u32) -> [u8; 3] {
-    let mut r: f64 = pixel_color.x();
-    let mut g: f64 = pixel_color.y();
-    let mut b: f64 = pixel_color.z();
-
-    // Divide the color by the number of samples.
-    let scale: f64 = 1.0 / samples_per_pixel as f64;
-    r = (scale * r).sqrt();
-    g = (scale * g).sqrt();
-    b = (scale * b).sqrt();
-
-    // Write the translated [0,255] value of each color component.
-    [
-        (256.0 * rt_weekend::clamp(r, 0.0, 0.999)) as u8,
-        (256.0 * rt_weekend::clamp(g, 0.0, 0.999)) as u8,
-        (256.0 * rt_weekend::clamp(b, 0.0, 0.999)) as u8,
-    ]
-}
-
 // fn random_scene() -> HittableList {
 //     let mut world = HittableList::new();
 
@@ -298,12 +274,7 @@ pub fn cornell_box() -> HittableList {
         white.clone(),
     )));
     objects.add(Arc::new(XyRect::construct(
-        0.0,
-        555.0,
-        0.0,
-        555.0,
-        555.0,
-        white,
+        0.0, 555.0, 0.0, 555.0, 555.0, white,
     )));
 
     // objects.add(Arc::new(Box::construct(
@@ -548,14 +519,26 @@ pub fn final_scene() -> HittableList {
 
     objects
 }
-pub fn test_city() -> HittableList {
+// Loads its mesh, so unlike the other scenes it comes with the files it was
+// read from and what was loaded of them.
+pub fn test_city() -> Result<Scene, LoadError> {
     // let mut center = Vec3::new(0.0, 0.0, 0.0);
     let albedo = Color3::construct(&[0.35, 0.35, 0.45]);
     // let mat_water = Lambertian::new_from_color(&water);
     // let mat_water = Dielectric::new(1.33);
     // let mat = Lambertian::new_from_color(&albedo);
-    let mut center = Point3::new();
-    let mut objects = load_new("City Islands", 1.0, &albedo, &mut center);
+    let path = PathBuf::from("objects/City Islands/City Islands.obj");
+    let city = load_obj(
+        &path,
+        &ObjOptions {
+            default_color: albedo,
+            ..ObjOptions::default()
+        },
+    )?;
+    let sources = city.sources.clone();
+    let summary = city.to_string();
+    let mut objects = HittableList::new();
+    objects.add(Arc::new(city));
     objects.add(Arc::new(XzRect::construct(
         -INFINITY,
         INFINITY,
@@ -581,135 +564,334 @@ pub fn test_city() -> HittableList {
     //     &Vec3::new(0., -50., 0.),
     // )));
     let world = BVHNode::construct(&objects.objects, 0.0, 1.0);
-    HittableList {
-        objects: vec![Arc::new(world)],
-    }
+    let mut scene = Scene::construct(
+        HittableList {
+            objects: vec![Arc::new(world)],
+        },
+        &Color3::construct(&[1.0, 1.0, 1.0]),
+        &(0.255 * Point3::construct(&[0.0, 1000.0, -4000.0])),
+        &Point3::construct(&[0.0, 0.0, 0.0]),
+        40.0,
+    );
+    scene.sources = sources;
+    scene.meshes.push((path, summary));
+    Ok(scene)
+}
+
+pub const SCENE_NAMES: [&str; 8] = [
+    "two_spheres",
+    "two_perlin_spheres",
+    "earth",
+    "simple_light",
+    "cornell_box",
+    "cornell_smoke",
+    "final_scene",
+    "test_city",
+];
+
+// `None` for an unknown name.
+pub fn select_scene(name: &str) -> Option<Result<Scene, LoadError>> {
+    let sky = Color3::construct(&[0.7, 0.8, 1.0]);
+    let black = Color3::construct(&[0.0, 0.0, 0.0]);
+    let scene = match name {
+        "two_spheres" | "two_perlin_spheres" | "earth" => {
+            let world = match name {
+                "two_spheres" => two_spheres(),
+                "two_perlin_spheres" => two_perlin_spheres(),
+                _ => earth(),
+            };
+            let mut scene = Scene::construct(
+                world,
+                &sky,
+                &Point3::construct(&[13.0, 2.0, 3.0]),
+                &Point3::construct(&[0.0, 0.0, 0.0]),
+                20.0,
+            );
+            if name == "earth" {
+                scene.sources.push(PathBuf::from("earthmap.jpg"));
+            }
+            scene
+        }
+        "simple_light" => Scene::construct(
+            simple_light(),
+            &black,
+            &Point3::construct(&[26.0, 3.0, 6.0]),
+            &Point3::construct(&[0.0, 2.0, 0.0]),
+            20.0,
+        ),
+        "cornell_box" | "cornell_smoke" => Scene::construct(
+            if name == "cornell_box" {
+                cornell_box()
+            } else {
+                cornell_smoke()
+            },
+            &black,
+            &Point3::construct(&[278.0, 278.0, -800.0]),
+            &Point3::construct(&[278.0, 278.0, 0.0]),
+            40.0,
+        ),
+        "final_scene" => Scene::construct(
+            final_scene(),
+            &black,
+            &Point3::construct(&[478.0, 278.0, -600.0]),
+            &Point3::construct(&[278.0, 278.0, 0.0]),
+            40.0,
+        ),
+        "test_city" => match test_city() {
+            Ok(scene) => scene,
+            Err(e) => return Some(Err(e)),
+        },
+        _ => return None,
+    };
+    Some(Ok(scene))
 }
 
 fn main() {
-    // let img =
+    let opts = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(Command::Render(opts)) => *opts,
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            println!("\nScenes: {}", SCENE_NAMES.join(", "));
+            exit(0);
+        }
+        Err(msg) => {
+            eprintln!("{} {}", style("error:").red(), msg);
+            eprintln!("Run with `--help` for usage.");
+            exit(2);
+        }
+    };
 
-    let path = std::path::Path::new("output/final.jpg");
-    let prefix = path.parent().unwrap();
-    std::fs::create_dir_all(prefix).expect("Cannot create all the parents");
+    let path = opts.output.as_path();
+    if let Some(prefix) = path.parent() {
+        std::fs::create_dir_all(prefix).expect("Cannot create all the parents");
+    }
 
     // Image
-    const ASPECT_RATIO: f64 = 16.0 / 9.0;
-    const IMAGE_WIDTH: u32 = 3840;
-    const IMAGE_HEIGHT: u32 = (IMAGE_WIDTH as f64 / ASPECT_RATIO) as u32;
-    const SAMPLES_PER_PIXEL: u32 = 16 * 200;
-    const MAX_DEPTH: i32 = 50;
+    let image_width = opts.image_width;
+    let image_height = opts.image_height;
+    let samples_per_pixel = opts.samples_per_pixel;
+    let max_depth = opts.max_depth;
 
     // World
-    let lookfrom = 0.255 * Point3::construct(&[0.0, 1000.0, -4000.0]);
-    let lookat = Point3::construct(&[0.0, 0.0, 0.0]);
-    let vfov = 40.0;
-    let aperture = 0.0;
-    // 135. / 256., 206. / 256., 235. / 256.
-    let background = Color3::construct(&[1.0, 1.0, 1.0]);
-    let mth = 1;
-    let world = match mth {
-        1 => {
-            test_city()
+    // Scene construction draws random numbers too (Perlin tables, BVH axes, ...).
+    seed_rng(opts.seed, u64::MAX);
+    bvh::configure(opts.bvh_split, opts.bvh_stats);
+    let scene = if let Some(scene_file) = &opts.scene_file {
+        match scene_file::load_scene(scene_file) {
+            Ok(scene) => scene,
+            Err(e) => {
+                eprintln!("{} {}", style("error:").red(), e);
+                exit(1);
+            }
         }
-        _ => {
-            final_scene()
+    } else if let Some(scene) = select_scene(&opts.scene) {
+        match scene {
+            Ok(scene) => scene,
+            Err(e) => {
+                eprintln!("{} {}", style("error:").red(), e);
+                exit(1);
+            }
         }
+    } else {
+        eprintln!(
+            "{} unknown scene `{}`, available: {}",
+            style("error:").red(),
+            opts.scene,
+            SCENE_NAMES.join(", ")
+        );
+        exit(2);
     };
+    for (path, mesh) in &scene.meshes {
+        println!("Loaded {}: {}", path.display(), mesh);
+    }
+    let background = scene.background;
 
     // Camera
-    // let lookfrom: Point3 = Point3::construct(&[13.0, 2.0, 3.0]);
-    // let lookat: Point3 = Point3::construct(&[0.0, 0.0, 0.0]);
-    let vup: Vec3 = Vec3::construct(&[0.0, 1.0, 0.0]);
-    let dist_to_focus: f64 = 10.0;
-
-    let cam: Camera = Camera::new(
-        &lookfrom,
-        &lookat,
-        &vup,
-        &[vfov, ASPECT_RATIO, aperture, dist_to_focus],
-        0.0,
-        1.0,
-    );
+    let cam: Camera = scene.camera(opts.aspect_ratio);
+    let lights = if opts.light_sampling {
+        scene.lights()
+    } else {
+        HittableList::new()
+    };
+    let world = scene.world;
+    let sources = scene.sources;
 
     // Render
-    let quality = 100;
-    let mut img: RgbImage = ImageBuffer::new(IMAGE_WIDTH, IMAGE_HEIGHT);
-
     let progress = if option_env!("CI").unwrap_or_default() == "true" {
         ProgressBar::hidden()
     } else {
-        ProgressBar::new((IMAGE_HEIGHT * IMAGE_WIDTH) as u64)
+        ProgressBar::new(0)
     };
 
-    let thread_num: u32 = 16;
-    for j in (0..IMAGE_HEIGHT).rev() {
-        for i in 0..IMAGE_WIDTH {
-            let pixel = img.get_pixel_mut(i, IMAGE_HEIGHT - j - 1);
-            let mut pixel_color: Color3 = Color3::construct(&[0.0, 0.0, 0.0]);
-
-            let mut handles: Vec<thread::JoinHandle<()>> = Vec::new();
-            let mut recv: Vec<mpsc::Receiver<Color3>> = Vec::new();
-            for _s in 0..thread_num {
-                let (tx, rx) = mpsc::channel();
-                recv.push(rx);
-                let cam = cam.clone();
-                let world = world.clone();
-                let max_depth = MAX_DEPTH;
-                let image_width = IMAGE_WIDTH;
-                let image_height = IMAGE_HEIGHT;
-                let i_f64 = i as f64;
-                let j_f64 = j as f64;
-
-                let handle = thread::spawn(move || {
-                    for _t in 0..(SAMPLES_PER_PIXEL / thread_num) {
-                        let u: f64 = (i_f64 + random_double()) / (image_width - 1) as f64;
-                        let v: f64 = (j_f64 + random_double()) / (image_height - 1) as f64;
-                        let r: Ray = cam.get_ray(u, v);
-                        let mut color = ray_color(&r, &background, &world, max_depth);
-                        if color.near_zero() {
-                            color = Color3::construct(&[0.65, 0.65, 0.65]);
-                        }
-                        // for _i in 0..3 {
-                        //     if color.e[_i] != color.e[_i] {
-                        //         color.e[_i] = 0.;
-                        //     }
-                        // }
-                        tx.send(color).unwrap();
-                    }
-                });
-                handles.push(handle);
-            }
-            let mut cnt = 0u32;
-            // 第一层遍历：遍历接收者
-            for rec in recv {
-                // 第二层遍历：遍历接收者收到的数据
-                for received in rec {
-                    pixel_color += received;
-                    cnt += 1;
+    let settings = RenderSettings {
+        image_width,
+        image_height,
+        samples_per_pixel,
+        pass_samples: opts.pass_samples,
+        time_limit: opts.time_limit,
+        min_samples: opts.min_samples,
+        max_samples: opts.max_samples,
+        max_error: opts.max_error,
+        sampler: opts.sampler,
+        filter: opts.filter,
+        max_depth,
+        roulette_depth: opts.roulette_depth,
+        clamp: opts.clamp,
+        clamp_indirect: opts.clamp_indirect,
+        thread_num: opts.thread_num,
+        tile_size: opts.tile_size,
+        seed: opts.seed,
+    };
+    let write_image = |film: &Film| {
+        if let Err(e) = save_image(
+            path,
+            opts.format,
+            opts.quality,
+            &opts.tone_mapping,
+            film.width,
+            film.height,
+            &film.average(),
+        ) {
+            println!("{} {}", style("Outputing image fails:").red(), e);
+        }
+    };
+
+    // A checkpoint is only resumed by a render of the same scene with the same
+    // settings.
+    let fingerprint = fingerprint(&opts, &sources);
+    let film = match &opts.checkpoint {
+        Some(checkpoint) if checkpoint.exists() => {
+            match checkpoint::load(checkpoint, fingerprint) {
+                Ok(film) => {
+                    println!(
+                        "Resuming \"{}\" after {} passes",
+                        style(checkpoint.display()).yellow(),
+                        film.passes
+                    );
+                    film
+                }
+                Err(e) => {
+                    eprintln!("{} {}", style("error:").red(), e);
+                    exit(1);
                 }
             }
-            for thread in handles {
-                thread.join().unwrap();
+        }
+        _ => Film::new(image_width, image_height),
+    };
+
+    // Progressive renders keep the output up to date as a preview.
+    let mut out_of_time = false;
+    let mut counts = SampleCounts::default();
+    let scene = RenderScene {
+        world: Arc::new(world),
+        lights: Arc::new(lights),
+        cam,
+        background,
+    };
+    let film = render(&scene, &settings, film, &progress, |film, report| {
+        if let Some(checkpoint) = &opts.checkpoint {
+            if let Err(e) = checkpoint::save(checkpoint, film, fingerprint) {
+                progress.println(format!(
+                    "{} cannot save checkpoint `{}`: {}",
+                    style("warning:").yellow(),
+                    checkpoint.display(),
+                    e
+                ));
             }
-            assert_eq!(cnt, SAMPLES_PER_PIXEL);
-            let rgb: [u8; 3] = write_color(&pixel_color, SAMPLES_PER_PIXEL);
-            *pixel = image::Rgb(rgb);
-            progress.inc(1);
         }
-    }
+        out_of_time = report.out_of_time;
+        counts = report.counts;
+        if report.last {
+            return;
+        }
+        write_image(film);
+        progress.println(if opts.max_error.is_some() {
+            format!(
+                "Pass {}: {:.1} spp on average, {} pixels left after {:.1}s",
+                report.pass + 1,
+                report.mean_samples,
+                report.active_pixels,
+                report.elapsed.as_secs_f64()
+            )
+        } else {
+            format!(
+                "Pass {}: {} spp after {:.1}s",
+                report.pass + 1,
+                report.samples_per_pixel,
+                report.elapsed.as_secs_f64()
+            )
+        });
+    });
     progress.finish();
+    let fewest = film.samples.iter().copied().min().unwrap_or(0);
+    let most = film.samples.iter().copied().max().unwrap_or(0);
+    if out_of_time {
+        // An adaptive render's `--spp` is a mean.
+        if opts.max_error.is_some() {
+            println!(
+                "Time limit reached at {:.1} of {} spp on average",
+                film.mean_samples(),
+                samples_per_pixel
+            );
+        } else {
+            println!(
+                "Time limit reached at {} of {} spp",
+                most, samples_per_pixel
+            );
+        }
+    }
+    if counts.nan > 0 || counts.infinite > 0 {
+        println!(
+            "{} {} samples were NaN and {} infinite, and were left out",
+            style("warning:").yellow(),
+            counts.nan,
+            counts.infinite
+        );
+    }
+    if counts.clamped > 0 {
+        println!("Clamped {} samples", counts.clamped);
+    }
+    if opts.max_error.is_some() {
+        println!(
+            "Adaptive sampling: {:.1} spp on average, {} to {}",
+            film.mean_samples(),
+            fewest,
+            most
+        );
+    }
+    // White for pixels with as many samples as a pixel can take.
+    if let Some((spp_path, spp_format)) = &opts.spp_image {
+        let spp: Vec<Color3> = film
+            .samples
+            .iter()
+            .map(|n| {
+                let k = *n as f64 / opts.max_samples as f64;
+                Color3::construct(&[k, k, k])
+            })
+            .collect();
+        let linear = ToneMapping {
+            operator: ToneMapper::Clamp,
+            exposure: 0.0,
+            white_point: None,
+        };
+        if let Err(e) = save_image(
+            spp_path,
+            *spp_format,
+            opts.quality,
+            &linear,
+            film.width,
+            film.height,
+            &spp,
+        ) {
+            println!("{} {}", style("Outputing image fails:").red(), e);
+        }
+    }
 
     println!(
         "Output image as \"{}\"",
         style(path.to_str().unwrap()).yellow()
     );
-    let output_image = image::DynamicImage::ImageRgb8(img);
-    let mut output_file = File::create(path).unwrap();
-    match output_image.write_to(&mut output_file, image::ImageOutputFormat::Jpeg(quality)) {
-        Ok(_) => {}
-        Err(_) => println!("{}", style("Outputing image fails.").red()),
-    }
+    write_image(&film);
 
     exit(0);
 }
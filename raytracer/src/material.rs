@@ -1,24 +1,49 @@
 use crate::hittable::HitRecord;
+use crate::pdf::{CosinePdf, Pdf, SpherePdf};
 use crate::ray::Ray;
-use crate::rt_weekend::random_double;
+use crate::rt_weekend::PI;
+use crate::sampler::sample_1d;
 use crate::texture::{SolidColor, Texture};
-use crate::vec3::{
-    dot, random_in_unit_sphere, random_unit_vector, reflect, refract, Color3, Point3, Vec3,
-};
+use crate::vec3::{dot, random_in_unit_sphere, reflect, refract, Color3, Point3, Vec3};
 use std::ops::Deref;
 use std::sync::Arc;
 
+// What `Material::scatter` did with an incoming ray. Specular materials pick
+// the one outgoing `specular_ray` themselves; the others describe the
+// distribution to draw it from with `pdf_ptr`, which lets the integrator mix
+// it with other strategies such as light sampling.
+pub struct ScatterRecord {
+    pub specular_ray: Ray,
+    pub is_specular: bool,
+    pub attenuation: Color3,
+    pub pdf_ptr: Option<Box<dyn Pdf>>,
+}
+
+impl ScatterRecord {
+    pub fn new() -> Self {
+        Self {
+            specular_ray: Ray::new(),
+            is_specular: false,
+            attenuation: Color3::new(),
+            pdf_ptr: None,
+        }
+    }
+}
+
 pub trait Material: Send + Sync {
-    fn scatter(
-        &self,
-        r_in: &Ray,
-        rec: &HitRecord,
-        attenuation: &mut Vec3,
-        scattered: &mut Ray,
-    ) -> bool;
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool;
     fn emitted(&self, _u: f64, _v: f64, _p: &Point3) -> Color3 {
         Color3::construct(&[0.0, 0.0, 0.0])
     }
+    // The scattering function is `attenuation * scattering_pdf`, for any
+    // direction `scattered`. Specular materials leave it at zero.
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        0.0
+    }
+    // Surfaces made of an emissive material are sampled as lights.
+    fn is_emissive(&self) -> bool {
+        false
+    }
 }
 
 pub struct Lambertian {
@@ -45,23 +70,20 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(
-        &self,
-        r_in: &Ray,
-        rec: &HitRecord,
-        attenuation: &mut Vec3,
-        scattered: &mut Ray,
-    ) -> bool {
-        let mut scatter_direction: Vec3 = rec.normal + random_unit_vector();
-
-        if scatter_direction.near_zero() {
-            scatter_direction = rec.normal;
-        }
-
-        *scattered = Ray::construct(&rec.p, &scatter_direction, r_in.time());
-        *attenuation = self.albedo.deref().value(rec.u, rec.v, &rec.p);
+    fn scatter(&self, _r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        srec.is_specular = false;
+        srec.attenuation = self.albedo.deref().value(rec.u, rec.v, &rec.p);
+        srec.pdf_ptr = Some(Box::new(CosinePdf::construct(&rec.normal)));
         true
     }
+    fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let cosine = dot(&rec.normal, &scattered.direction().unit());
+        if cosine < 0.0 {
+            0.0
+        } else {
+            cosine / PI
+        }
+    }
 }
 
 pub struct Metal {
@@ -85,27 +107,26 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(
-        &self,
-        r_in: &Ray,
-        rec: &HitRecord,
-        attenuation: &mut Vec3,
-        scattered: &mut Ray,
-    ) -> bool {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
         let reflected: Vec3 = reflect(&r_in.direction().unit(), &rec.normal);
-        *scattered = Ray::construct(
+        srec.specular_ray = Ray::construct(
             &rec.p,
             &(reflected + self.fuzz * random_in_unit_sphere()),
             r_in.time(),
         );
-        *attenuation = self.albedo;
-        dot(&scattered.direction(), &rec.normal) > 0.0
+        srec.is_specular = true;
+        srec.attenuation = self.albedo;
+        srec.pdf_ptr = None;
+        dot(&srec.specular_ray.direction(), &rec.normal) > 0.0
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Dielectric {
     ir: f64, // Index of Refraction
+    // Absorption coefficient of each channel, per unit of distance travelled
+    // inside. `None` for clear glass.
+    absorption: Option<Color3>,
 }
 
 impl Dielectric {
@@ -113,7 +134,23 @@ impl Dielectric {
     //     Self { ir: 1.0 }
     // }
     pub fn construct(ir: f64) -> Self {
-        Self { ir }
+        Self {
+            ir,
+            absorption: None,
+        }
+    }
+    // Tinted glass or liquid: light that travels `1 / density` inside keeps
+    // the fraction `tint` of each channel, following the Beer-Lambert law.
+    pub fn construct_tinted(ir: f64, tint: &Color3, density: f64) -> Self {
+        let coefficient = |c: f64| -c.max(1e-6).ln() * density;
+        Self {
+            ir,
+            absorption: Some(Color3::construct(&[
+                coefficient(tint.x()),
+                coefficient(tint.y()),
+                coefficient(tint.z()),
+            ])),
+        }
     }
 
     pub fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
@@ -124,14 +161,21 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(
-        &self,
-        r_in: &Ray,
-        rec: &HitRecord,
-        attenuation: &mut Vec3,
-        scattered: &mut Ray,
-    ) -> bool {
-        *attenuation = Vec3::construct(&[0.0, 0.0, 1.0]);
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        srec.is_specular = true;
+        srec.pdf_ptr = None;
+        srec.attenuation = Color3::construct(&[1.0, 1.0, 1.0]);
+        // Hitting the back of the surface means the ray travelled inside.
+        // This assumes it entered through the same object, so dielectrics
+        // should not overlap.
+        if let (Some(absorption), false) = (&self.absorption, rec.front_face) {
+            let distance = rec.t * r_in.direction().length();
+            srec.attenuation = Color3::construct(&[
+                (-absorption.x() * distance).exp(),
+                (-absorption.y() * distance).exp(),
+                (-absorption.z() * distance).exp(),
+            ]);
+        }
         let refraction_ratio = if rec.front_face {
             1.0 / self.ir
         } else {
@@ -145,13 +189,13 @@ impl Material for Dielectric {
         let cannot_refract: bool = refraction_ratio * sin_theta > 1.0;
 
         let direction: Vec3 =
-            if cannot_refract || Self::reflectance(cos_theta, refraction_ratio) > random_double() {
+            if cannot_refract || Self::reflectance(cos_theta, refraction_ratio) > sample_1d() {
                 reflect(&unit_direction, &rec.normal)
             } else {
                 refract(&unit_direction, &rec.normal, refraction_ratio)
             };
 
-        *scattered = Ray::construct(&rec.p, &direction, r_in.time());
+        srec.specular_ray = Ray::construct(&rec.p, &direction, r_in.time());
         true
     }
 }
@@ -160,11 +204,11 @@ pub struct DiffuseLight {
     emit: Arc<dyn Texture>,
 }
 impl DiffuseLight {
-    // pub fn construct(emit: Arc<dyn Texture>) -> Self {
-    //     Self {
-    //         emit: Arc::clone(&emit),
-    //     }
-    // }
+    pub fn construct(emit: Arc<dyn Texture>) -> Self {
+        Self {
+            emit: Arc::clone(&emit),
+        }
+    }
     pub fn construct_color(emit: &Color3) -> Self {
         Self {
             emit: Arc::new(SolidColor::construct(emit)),
@@ -172,29 +216,26 @@ impl DiffuseLight {
     }
 }
 impl Material for DiffuseLight {
-    fn scatter(
-        &self,
-        _r_in: &Ray,
-        _rec: &HitRecord,
-        _attenuation: &mut Vec3,
-        _scattered: &mut Ray,
-    ) -> bool {
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord, _srec: &mut ScatterRecord) -> bool {
         false
     }
     fn emitted(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
         self.emit.deref().value(u, v, p)
     }
+    fn is_emissive(&self) -> bool {
+        true
+    }
 }
 
 pub struct Isotropic {
     albedo: Arc<dyn Texture>,
 }
 impl Isotropic {
-    // pub fn construct(albedo: Arc<dyn Texture>) -> Self {
-    //     Self {
-    //         albedo: Arc::clone(&albedo),
-    //     }
-    // }
+    pub fn construct(albedo: Arc<dyn Texture>) -> Self {
+        Self {
+            albedo: Arc::clone(&albedo),
+        }
+    }
     pub fn construct_color(albedo: &Color3) -> Self {
         Self {
             albedo: Arc::new(SolidColor::construct(albedo)),
@@ -202,15 +243,13 @@ impl Isotropic {
     }
 }
 impl Material for Isotropic {
-    fn scatter(
-        &self,
-        r_in: &Ray,
-        rec: &HitRecord,
-        attenuation: &mut Vec3,
-        scattered: &mut Ray,
-    ) -> bool {
-        *scattered = Ray::construct(&rec.p, &random_in_unit_sphere(), r_in.time());
-        *attenuation = self.albedo.deref().value(rec.u, rec.v, &rec.p);
+    fn scatter(&self, _r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        srec.is_specular = false;
+        srec.attenuation = self.albedo.deref().value(rec.u, rec.v, &rec.p);
+        srec.pdf_ptr = Some(Box::new(SpherePdf));
         true
     }
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        1.0 / (4.0 * PI)
+    }
 }
@@ -35,26 +35,35 @@ impl MovingSphere {
         self.center0
             + ((time - self.time0) / (self.time1 - self.time0)) * (self.center1 - self.center0)
     }
-}
-
-impl Hittable for MovingSphere {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    // The nearest ray parameter in (t_min, t_max) where the ray meets the
+    // sphere.
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
         let oc: Vec3 = r.origin() - self.center(r.time());
         let a: f64 = r.direction().length_squared();
         let half_b: f64 = dot(&oc, &r.direction());
         let c: f64 = oc.length_squared() - self.radius * self.radius;
         let det: f64 = half_b * half_b - a * c;
         if det < 0.0 {
-            return false;
+            return None;
         }
         let sqrtd: f64 = det.sqrt();
         let mut root: f64 = (-half_b - sqrtd) / a;
         if root < t_min || t_max < root {
             root = (-half_b + sqrtd) / a;
             if root < t_min || t_max < root {
-                return false;
+                return None;
             }
         }
+        Some(root)
+    }
+}
+
+impl Hittable for MovingSphere {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let root = match self.intersect(r, t_min, t_max) {
+            Some(root) => root,
+            None => return false,
+        };
         rec.t = root;
         rec.p = r.at(rec.t);
         let outward_normal: Vec3 = (rec.p - self.center(r.time())) / self.radius;
@@ -62,6 +71,9 @@ impl Hittable for MovingSphere {
         rec.mat_ptr = Some(Arc::clone(&self.mat_ptr));
         true
     }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
+    }
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut crate::aabb::Aabb) -> bool {
         let box0 = crate::aabb::Aabb::construct(
             &(self.center(time0) - Vec3::construct(&[self.radius, self.radius, self.radius])),
@@ -1,11 +1,19 @@
-use crate::bvh::BVHNode;
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
 use crate::hittable_list::HittableList;
-use crate::material::Lambertian;
+use crate::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use crate::ray::Ray;
+use crate::rt_weekend::INFINITY;
 use crate::texture::ImageTexture;
-use crate::triangle::Triangle;
+use crate::triangle_mesh::{MeshTriangle, TriangleMesh};
 use crate::vec3::*;
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tobj::{load_obj, LoadOptions};
+use tobj::LoadOptions;
 
 // pub fn load_objects(
 //     pathname: &str,
@@ -77,101 +85,473 @@ use tobj::{load_obj, LoadOptions};
 //     list
 // }
 
-pub fn load_new(project_name: &str, scale: f64, col: &Color3, center: &mut Point3) -> HittableList {
-    // .mtl
+// Used when a model has no normals of its own. Faces meeting at a sharper angle,
+// in degrees, keep a hard edge between them.
+pub const CREASE_ANGLE: f64 = 60.0;
 
-    let pre_path = format!("objects/{}/", project_name);
-    let obj_path = format!("{}{}.obj", pre_path, project_name);
-    let (models, materials) = load_obj(
-        obj_path,
+// Normals for a model without its own: the sum of the faces around each
+// vertex, weighted by their area, leaving out those that turn by more than
+// `crease_angle` from the face of the corner. The corners of a vertex share a
+// normal when they sum the same faces, so a smooth surface has one per vertex
+// and only creases split them. Zero-area faces have no direction: they are left
+// out of the sums and get no normals of their own.
+fn vertex_normals(
+    points: &[Point3],
+    indices: &[u32],
+    crease_angle: f64,
+) -> (Vec<Vec3>, Vec<Option<[u32; 3]>>) {
+    let face_count = indices.len() / 3;
+    let corner = |face: usize, j: usize| indices[face * 3 + j] as usize;
+    // The cross product has the length of twice the area.
+    let face_normals: Vec<Vec3> = (0..face_count)
+        .map(|face| {
+            let a = points[corner(face, 0)];
+            cross(
+                &(points[corner(face, 1)] - a),
+                &(points[corner(face, 2)] - a),
+            )
+        })
+        .collect();
+    let degenerate = |face: usize| face_normals[face].length_squared() == 0.0;
+    let mut adjacent: Vec<Vec<usize>> = vec![Vec::new(); points.len()];
+    for face in (0..face_count).filter(|face| !degenerate(*face)) {
+        for j in 0..3 {
+            adjacent[corner(face, j)].push(face);
+        }
+    }
+
+    let cos_crease = crease_angle.to_radians().cos();
+    let mut normals = Vec::new();
+    // The faces summed into each normal of a vertex so far, and its index.
+    let mut splits: Vec<Vec<(Vec<usize>, u32)>> = vec![Vec::new(); points.len()];
+    let mut face_indices = Vec::with_capacity(face_count);
+    for face in 0..face_count {
+        if degenerate(face) {
+            face_indices.push(None);
+            continue;
+        }
+        let n = face_normals[face].unit();
+        let mut corners = [0; 3];
+        for (j, index) in corners.iter_mut().enumerate() {
+            let vertex = corner(face, j);
+            let faces: Vec<usize> = adjacent[vertex]
+                .iter()
+                .copied()
+                .filter(|&other| {
+                    other == face || dot(&n, &face_normals[other].unit()) >= cos_crease
+                })
+                .collect();
+            *index = match splits[vertex].iter().find(|split| split.0 == faces) {
+                Some(split) => split.1,
+                None => {
+                    let mut sum = Vec3::new();
+                    for &other in &faces {
+                        sum += face_normals[other];
+                    }
+                    normals.push(sum);
+                    let index = normals.len() as u32 - 1;
+                    splits[vertex].push((faces, index));
+                    index
+                }
+            };
+        }
+        face_indices.push(Some(corners));
+    }
+    (normals, face_indices)
+}
+
+// An MTL colour, written as `r g b` or as a single grey value.
+fn parse_color(s: &str) -> Option<Color3> {
+    let values: Vec<f64> = s
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .ok()?;
+    match values[..] {
+        [r, g, b] => Some(Color3::construct(&[r, g, b])),
+        [x] => Some(Color3::construct(&[x, x, x])),
+        _ => None,
+    }
+}
+
+// Picks the closest of our materials for an MTL material. Emission (Ke) comes
+// first, then transparency (d, Tr or a glass illum mode) as a Dielectric with
+// the Ni index, then the reflective illum modes as a Metal whose fuzz follows
+// the Ns exponent, and everything else is Lambertian with map_Kd or Kd.
+// `default_color` stands in for a missing Kd. The textures it loads are added
+// to `sources`.
+fn mtl_material(
+    mtl: &tobj::Material,
+    dir: &Path,
+    default_color: &Color3,
+    sources: &mut Vec<PathBuf>,
+) -> Result<Arc<dyn Material>, LoadError> {
+    let param = |key: &str| mtl.unknown_param.get(key);
+    if let Some(emit) = param("Ke").and_then(|ke| parse_color(ke)) {
+        if !emit.near_zero() {
+            return Ok(Arc::new(DiffuseLight::construct_color(&emit)));
+        }
+    }
+
+    let illum = mtl.illumination_model.unwrap_or(2);
+    let dissolve = match (
+        mtl.dissolve,
+        param("Tr").and_then(|tr| tr.trim().parse::<f64>().ok()),
+    ) {
+        (Some(d), _) => d,
+        (None, Some(tr)) => 1.0 - tr,
+        (None, None) => 1.0,
+    };
+    if dissolve < 1.0 || matches!(illum, 4 | 6 | 7 | 9) {
+        // Exporters often write Ni 0 when they mean "not set".
+        let ir = match mtl.optical_density {
+            Some(ni) if ni >= 1.0 => ni,
+            _ => 1.5,
+        };
+        return Ok(match param("Tf").and_then(|tf| parse_color(tf)) {
+            Some(tint) if tint.e.iter().all(|c| (0.0..1.0).contains(c)) => {
+                Arc::new(Dielectric::construct_tinted(ir, &tint, 1.0))
+            }
+            _ => Arc::new(Dielectric::construct(ir)),
+        });
+    }
+
+    let diffuse = mtl.diffuse.map(|kd| Color3::construct(&kd));
+    if matches!(illum, 3 | 5 | 8) {
+        let albedo = match mtl.specular.map(|ks| Color3::construct(&ks)) {
+            Some(ks) if !ks.near_zero() => ks,
+            _ => diffuse.unwrap_or(*default_color),
+        };
+        // The width of a Phong lobe with exponent Ns, roughly.
+        let fuzz = match mtl.shininess {
+            Some(ns) => (2.0 / (ns.max(0.0) + 2.0)).sqrt(),
+            None => 0.0,
+        };
+        return Ok(Arc::new(Metal::construct(&albedo, fuzz)));
+    }
+
+    Ok(match &mtl.diffuse_texture {
+        Some(texture_name) => {
+            let path = texture_path(dir, texture_name);
+            let texture =
+                ImageTexture::load(&path).map_err(|e| LoadError::Texture(path.clone(), e))?;
+            sources.push(path);
+            Arc::new(Lambertian::construct_texture(Arc::new(texture)))
+        }
+        None => Arc::new(Lambertian::construct(&diffuse.unwrap_or(*default_color))),
+    })
+}
+
+// Where to put a model, and what to do about missing or unwanted data.
+#[derive(Clone)]
+pub struct ObjOptions {
+    pub scale: f64,
+    // Degrees about the x, then the y, then the z axis, after scaling.
+    pub rotate: Vec3,
+    // Applied last.
+    pub translate: Vec3,
+    // Replaces the MTL materials of every face when set. The MTL libraries are
+    // then not read at all, and need not exist.
+    pub material: Option<Arc<dyn Material>>,
+    // For faces without a material and MTL materials without Kd.
+    pub default_color: Color3,
+    // For models whose texture coordinates count v from the top.
+    pub flip_v: bool,
+    pub crease_angle: f64,
+}
+
+impl Default for ObjOptions {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            rotate: Vec3::new(),
+            translate: Vec3::new(),
+            material: None,
+            default_color: Color3::construct(&[0.5, 0.5, 0.5]),
+            flip_v: false,
+            crease_angle: CREASE_ANGLE,
+        }
+    }
+}
+
+impl ObjOptions {
+    fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let (sin_x, cos_x) = self.rotate.x().to_radians().sin_cos();
+        let (sin_y, cos_y) = self.rotate.y().to_radians().sin_cos();
+        let (sin_z, cos_z) = self.rotate.z().to_radians().sin_cos();
+        let v = Vec3::construct(&[
+            v.x(),
+            cos_x * v.y() - sin_x * v.z(),
+            sin_x * v.y() + cos_x * v.z(),
+        ]);
+        let v = Vec3::construct(&[
+            cos_y * v.x() + sin_y * v.z(),
+            v.y(),
+            -sin_y * v.x() + cos_y * v.z(),
+        ]);
+        Vec3::construct(&[
+            cos_z * v.x() - sin_z * v.y(),
+            sin_z * v.x() + cos_z * v.y(),
+            v.z(),
+        ])
+    }
+    fn transform_point(&self, p: &Point3) -> Point3 {
+        self.transform_vector(&(*p * self.scale)) + self.translate
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    // The OBJ file cannot be opened.
+    Io(PathBuf, io::Error),
+    Obj(PathBuf, tobj::LoadError),
+    // The path of the OBJ file that refers to the MTL library.
+    Mtl(PathBuf, tobj::LoadError),
+    Texture(PathBuf, image::ImageError),
+    Empty(PathBuf),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(path, e) => write!(f, "cannot open `{}`: {}", path.display(), e),
+            LoadError::Obj(path, e) => write!(f, "cannot load `{}`: {}", path.display(), e),
+            LoadError::Mtl(path, e) => write!(
+                f,
+                "cannot load the materials of `{}`: {}",
+                path.display(),
+                e
+            ),
+            LoadError::Texture(path, e) => {
+                write!(f, "cannot load texture `{}`: {}", path.display(), e)
+            }
+            LoadError::Empty(path) => write!(f, "`{}` has no triangles", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+// A loaded model, already in world space.
+pub struct Mesh {
+    pub triangles: TriangleMesh,
+    // Of the vertices.
+    pub bounds: Aabb,
+    // The mean of the vertices.
+    pub centroid: Point3,
+    // The files it was read from: the OBJ file, its MTL libraries and their
+    // textures.
+    pub sources: Vec<PathBuf>,
+}
+
+impl fmt::Display for Mesh {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} triangles, bounds ({}) to ({}), centroid ({})",
+            self.triangles.len(),
+            self.bounds.minimum(),
+            self.bounds.maximum(),
+            self.centroid
+        )
+    }
+}
+
+impl Hittable for Mesh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        self.triangles.hit(r, t_min, t_max, rec)
+    }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.triangles.occluded(r, t_min, t_max)
+    }
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut Aabb) -> bool {
+        self.triangles.bounding_box(time0, time1, output_box)
+    }
+    fn collect_lights(&self, lights: &mut HittableList) {
+        self.triangles.collect_lights(lights);
+    }
+}
+
+// Texture paths are relative to the model, even when they start with a slash
+// or use Windows separators, as exporters often write them.
+fn texture_path(dir: &Path, name: &str) -> PathBuf {
+    let name = name.replace('\\', "/");
+    dir.join(name.trim_start_matches('/'))
+}
+
+pub fn load_obj<P: AsRef<Path>>(path: P, options: &ObjOptions) -> Result<Mesh, LoadError> {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let file = File::open(path).map_err(|e| LoadError::Io(path.to_path_buf(), e))?;
+    // As `tobj::load_obj` does, but keeping track of the MTL libraries.
+    let libraries = RefCell::new(Vec::new());
+    let (models, materials) = tobj::load_obj_buf(
+        &mut BufReader::new(file),
         &LoadOptions {
             single_index: false,
             triangulate: true,
             ignore_points: true,
             ignore_lines: true,
         },
+        |library| {
+            if options.material.is_some() {
+                return Ok((Vec::new(), Default::default()));
+            }
+            let library = dir.join(library);
+            libraries.borrow_mut().push(library.clone());
+            tobj::load_mtl(library)
+        },
     )
-    .expect("Failed to load .obj file.");
-
-    // mtl
-    let materials = materials.expect("Failed to load .mtl file.");
-    let mut text: Vec<ImageTexture> = Vec::new();
-    for mtl in materials {
-        if let Some(texure_name) = mtl.diffuse_texture {
-            let texure_path = format!("{}{}", pre_path, texure_name);
-            let tex = ImageTexture::construct(&texure_path);
-            text.push(tex);
-        } else {
-            text.push(ImageTexture::new());
-        }
-    }
-    let mut objects = HittableList::new();
-    let default_mat = Lambertian::construct(col);
-    let default_mat_ptr = Arc::new(default_mat);
-    let mut cnt = 0;
-    let mut count_def = 0;
+    .map_err(|e| LoadError::Obj(path.to_path_buf(), e))?;
+    let mut sources = vec![path.to_path_buf()];
+    sources.append(&mut libraries.into_inner());
+
+    let materials: Vec<Arc<dyn Material>> = match &options.material {
+        Some(material) => vec![material.clone()],
+        None => materials
+            .map_err(|e| LoadError::Mtl(path.to_path_buf(), e))?
+            .iter()
+            .map(|mtl| mtl_material(mtl, dir, &options.default_color, &mut sources))
+            .collect::<Result<_, _>>()?,
+    };
+
+    // Unassigned faces use the default material, kept after the others.
+    let mut materials = materials;
+    let default_id = materials.len() as u32;
+    materials.push(Arc::new(Lambertian::construct(&options.default_color)));
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut triangles = Vec::new();
+    let mut centroid = Point3::new();
+    let mut min = Point3::construct(&[INFINITY, INFINITY, INFINITY]);
+    let mut max = Point3::construct(&[-INFINITY, -INFINITY, -INFINITY]);
 
     for m in models {
-        let positions = &m.mesh.positions;
         let ind = &m.mesh.indices;
-        let text_coordinates = &m.mesh.texcoords;
-        let text_ind = &m.mesh.texcoord_indices;
-        let mut points = Vec::new();
-        let mut triangles = HittableList::new();
-        for i in (0..positions.len()).step_by(3) {
-            points.push(
-                Point3::construct(&[positions[i], positions[i + 1], positions[i + 2]]) * scale,
+        if ind.len() < 3 {
+            continue;
+        }
+        let position_offset = positions.len() as u32;
+        for p in m.mesh.positions.chunks_exact(3) {
+            let p = options.transform_point(&Point3::construct(&[p[0], p[1], p[2]]));
+            for c in 0..3 {
+                min.e[c] = min.e[c].min(p.e[c]);
+                max.e[c] = max.e[c].max(p.e[c]);
+            }
+            centroid += p;
+            positions.push(p);
+        }
+
+        // Either the file's normals with its own indices, or computed ones.
+        let normal_offset = normals.len() as u32;
+        let file_normals = !m.mesh.normals.is_empty() && m.mesh.normal_indices.len() == ind.len();
+        let mut face_normals = Vec::new();
+        if file_normals {
+            normals.extend(
+                m.mesh
+                    .normals
+                    .chunks_exact(3)
+                    .map(|n| options.transform_vector(&Vec3::construct(&[n[0], n[1], n[2]]))),
+            );
+        } else {
+            let (computed, faces) = vertex_normals(
+                &positions[position_offset as usize..],
+                ind,
+                options.crease_angle,
             );
-            cnt += 1;
-            *center +=
-                Point3::construct(&[positions[i], positions[i + 1], positions[i + 2]]) * scale;
+            normals.extend(computed);
+            face_normals = faces;
         }
 
-        // important
-        let cur_text = text[m.mesh.material_id.unwrap()].clone();
-        let has_text = !cur_text.empty();
-        let mat = Lambertian::construct_texture(Arc::new(cur_text));
-        let mat_ptr = Arc::new(mat);
+        let uv_offset = uvs.len() as u32;
+        let has_uvs = !m.mesh.texcoords.is_empty() && m.mesh.texcoord_indices.len() == ind.len();
+        if has_uvs {
+            uvs.extend(
+                m.mesh
+                    .texcoords
+                    .chunks_exact(2)
+                    .map(|t| (t[0], if options.flip_v { 1.0 - t[1] } else { t[1] })),
+            );
+        }
+
+        let material = match (&options.material, m.mesh.material_id) {
+            (Some(_), _) => 0,
+            (None, Some(id)) if (id as u32) < default_id => id as u32,
+            _ => default_id,
+        };
         for i in 0..ind.len() / 3 {
-            let mut uv = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)];
-            if !text_coordinates.is_empty() {
-                for j in 0..3 {
-                    let index = text_ind[i * 3 + j] as usize;
-                    uv[j] = (text_coordinates[index * 2], text_coordinates[index * 2 + 1]);
-                }
-            }
-            if has_text {
-                triangles.add(Arc::new(Triangle::new(
-                    &points[ind[i * 3] as usize],
-                    &points[ind[i * 3 + 1] as usize],
-                    &points[ind[i * 3 + 2] as usize],
-                    mat_ptr.clone(),
-                    uv[0],
-                    uv[1],
-                    uv[2],
-                )));
-            } else {
-                count_def += 1;
-                triangles.add(Arc::new(Triangle::new(
-                    &points[ind[i * 3] as usize],
-                    &points[ind[i * 3 + 1] as usize],
-                    &points[ind[i * 3 + 2] as usize],
-                    default_mat_ptr.clone(),
-                    uv[0],
-                    uv[1],
-                    uv[2],
-                )));
-            }
+            let corners =
+                |indices: &[u32], offset: u32| [0, 1, 2].map(|j| offset + indices[i * 3 + j]);
+            triangles.push(MeshTriangle {
+                positions: corners(ind, position_offset),
+                normals: if file_normals {
+                    Some(corners(&m.mesh.normal_indices, normal_offset))
+                } else {
+                    face_normals[i].map(|face| face.map(|n| normal_offset + n))
+                },
+                uvs: if has_uvs {
+                    Some(corners(&m.mesh.texcoord_indices, uv_offset))
+                } else {
+                    None
+                },
+                material,
+            });
         }
-        objects.add(Arc::new(BVHNode::new(&triangles, 0.0, 1.0)));
-    }
-    println!("{}", count_def);
-    *center /= cnt as f64;
-    if objects.objects.len() < 4 {
-        objects
-    } else {
-        let mut list = HittableList::new();
-        list.add(Arc::new(BVHNode::new(&objects, 0., 1.)));
-        list
+    }
+    if triangles.is_empty() {
+        return Err(LoadError::Empty(path.to_path_buf()));
+    }
+
+    let centroid = centroid / positions.len() as f64;
+    Ok(Mesh {
+        triangles: TriangleMesh::construct(positions, normals, uvs, triangles, materials),
+        bounds: Aabb::construct(&min, &max),
+        centroid,
+        sources,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f64, y: f64, z: f64) -> Point3 {
+        Point3::construct(&[x, y, z])
+    }
+
+    #[test]
+    fn vertex_normals_are_shared_but_at_creases() {
+        // A flat grid of 3 by 3 vertices: one normal each.
+        let grid: Vec<Point3> = (0..9)
+            .map(|i| point((i % 3) as f64, (i / 3) as f64, 0.0))
+            .collect();
+        let mut indices = Vec::new();
+        for cell in [0, 1, 3, 4] {
+            indices.extend_from_slice(&[cell, cell + 1, cell + 4, cell, cell + 4, cell + 3]);
+        }
+        let (normals, faces) = vertex_normals(&grid, &indices, CREASE_ANGLE);
+        assert_eq!(normals.len(), 9);
+        assert_eq!(faces.len(), 8);
+        assert!(faces.iter().all(|f| f.is_some()));
+
+        // Two faces folded at a right angle: the edge is split.
+        let fold = [
+            point(0.0, 0.0, 0.0),
+            point(0.0, 1.0, 0.0),
+            point(1.0, 0.0, 0.0),
+            point(0.0, 0.0, 1.0),
+        ];
+        let (normals, _) = vertex_normals(&fold, &[0, 1, 2, 0, 3, 1], CREASE_ANGLE);
+        assert_eq!(normals.len(), 6);
+        let (normals, _) = vertex_normals(&fold, &[0, 1, 2, 0, 3, 1], 100.0);
+        assert_eq!(normals.len(), 4);
+
+        // A zero-area face gets no normals and leaves the others alone.
+        let (normals, faces) = vertex_normals(&fold, &[0, 1, 2, 0, 0, 3], CREASE_ANGLE);
+        assert_eq!(faces[1], None);
+        assert_eq!(normals.len(), 3);
+        assert!(normals.iter().all(|n| n.length_squared() > 0.0));
     }
 }
@@ -0,0 +1,34 @@
+use crate::vec3::{cross, Vec3};
+
+// Orthonormal basis, with `w` along the vector it was built from.
+#[derive(Clone, Copy, Debug)]
+pub struct Onb {
+    pub axis: [Vec3; 3],
+}
+
+impl Onb {
+    pub fn build_from_w(n: &Vec3) -> Self {
+        let w = n.unit();
+        let a = if w.x().abs() > 0.9 {
+            Vec3::construct(&[0.0, 1.0, 0.0])
+        } else {
+            Vec3::construct(&[1.0, 0.0, 0.0])
+        };
+        let v = cross(&w, &a).unit();
+        let u = cross(&w, &v);
+        Self { axis: [u, v, w] }
+    }
+    pub fn u(&self) -> Vec3 {
+        self.axis[0]
+    }
+    pub fn v(&self) -> Vec3 {
+        self.axis[1]
+    }
+    pub fn w(&self) -> Vec3 {
+        self.axis[2]
+    }
+    // Local coordinates `a` to world space.
+    pub fn local(&self, a: &Vec3) -> Vec3 {
+        a.x() * self.u() + a.y() * self.v() + a.z() * self.w()
+    }
+}
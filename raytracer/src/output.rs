@@ -0,0 +1,60 @@
+use crate::cli::OutputFormat;
+use crate::tonemap::ToneMapping;
+use crate::vec3::Color3;
+use image::codecs::hdr::HdrEncoder;
+use image::{ImageBuffer, ImageResult, Rgb, RgbImage};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+// Radiance RGBE, keeps the full dynamic range of the render.
+fn save_hdr(path: &Path, width: u32, height: u32, pixels: &[Color3]) -> ImageResult<()> {
+    let data: Vec<Rgb<f32>> = pixels
+        .iter()
+        .map(|c| Rgb([c.x() as f32, c.y() as f32, c.z() as f32]))
+        .collect();
+    let file = BufWriter::new(File::create(path)?);
+    HdrEncoder::new(file).encode(&data, width as usize, height as usize)
+}
+
+// Portable float map: a tiny header followed by raw little-endian floats,
+// scanlines stored from the bottom of the image up.
+fn save_pfm(path: &Path, width: u32, height: u32, pixels: &[Color3]) -> ImageResult<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "PF\n{} {}\n-1.0\n", width, height)?;
+    for row in pixels.chunks(width as usize).rev() {
+        for c in row {
+            for channel in c.e {
+                file.write_all(&(channel as f32).to_le_bytes())?;
+            }
+        }
+    }
+    file.flush()?;
+    Ok(())
+}
+
+// `pixels` holds the linear radiance of each pixel, row by row from the top.
+// Tone mapping only applies to the 8-bit formats.
+pub fn save_image(
+    path: &Path,
+    format: OutputFormat,
+    quality: u8,
+    tone_mapping: &ToneMapping,
+    width: u32,
+    height: u32,
+    pixels: &[Color3],
+) -> ImageResult<()> {
+    let ldr_format = match format {
+        OutputFormat::Hdr => return save_hdr(path, width, height, pixels),
+        OutputFormat::Pfm => return save_pfm(path, width, height, pixels),
+        OutputFormat::Jpeg => image::ImageOutputFormat::Jpeg(quality),
+        OutputFormat::Png => image::ImageOutputFormat::Png,
+    };
+    let tone_mapping = tone_mapping.prepare(pixels);
+    let mut img: RgbImage = ImageBuffer::new(width, height);
+    for (pixel, color) in img.pixels_mut().zip(pixels) {
+        *pixel = Rgb(tone_mapping.encode_srgb8(color));
+    }
+    let mut output_file = File::create(path)?;
+    image::DynamicImage::ImageRgb8(img).write_to(&mut output_file, ldr_format)
+}
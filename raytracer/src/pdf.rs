@@ -0,0 +1,99 @@
+use crate::hittable::Hittable;
+use crate::onb::Onb;
+use crate::rt_weekend::PI;
+use crate::sampler::{sample_1d, sample_2d};
+use crate::vec3::{dot, random_unit_vector, Point3, Vec3};
+
+// A distribution of directions that can be sampled with `generate` and
+// evaluated, as a density over solid angle, with `value`.
+pub trait Pdf {
+    fn value(&self, direction: &Vec3) -> f64;
+    fn generate(&self) -> Vec3;
+}
+
+// Cosine-weighted directions about a surface normal.
+pub struct CosinePdf {
+    uvw: Onb,
+}
+impl CosinePdf {
+    pub fn construct(w: &Vec3) -> Self {
+        Self {
+            uvw: Onb::build_from_w(w),
+        }
+    }
+}
+impl Pdf for CosinePdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        let cosine = dot(&direction.unit(), &self.uvw.w());
+        if cosine <= 0.0 {
+            0.0
+        } else {
+            cosine / PI
+        }
+    }
+    fn generate(&self) -> Vec3 {
+        self.uvw.local(&random_cosine_direction())
+    }
+}
+
+// Uniform over all directions.
+pub struct SpherePdf;
+impl Pdf for SpherePdf {
+    fn value(&self, _direction: &Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+    fn generate(&self) -> Vec3 {
+        random_unit_vector()
+    }
+}
+
+// Directions from `o` towards the points of an object, see `Hittable::random`.
+pub struct HittablePdf<'a> {
+    o: Point3,
+    ptr: &'a dyn Hittable,
+}
+impl<'a> HittablePdf<'a> {
+    pub fn construct(ptr: &'a dyn Hittable, origin: &Point3) -> Self {
+        Self { o: *origin, ptr }
+    }
+}
+impl Pdf for HittablePdf<'_> {
+    fn value(&self, direction: &Vec3) -> f64 {
+        self.ptr.pdf_value(&self.o, direction)
+    }
+    fn generate(&self) -> Vec3 {
+        self.ptr.random(&self.o)
+    }
+}
+
+// An even mix of two distributions.
+pub struct MixturePdf<'a> {
+    p: [&'a dyn Pdf; 2],
+}
+impl<'a> MixturePdf<'a> {
+    pub fn construct(p0: &'a dyn Pdf, p1: &'a dyn Pdf) -> Self {
+        Self { p: [p0, p1] }
+    }
+}
+impl Pdf for MixturePdf<'_> {
+    fn value(&self, direction: &Vec3) -> f64 {
+        0.5 * self.p[0].value(direction) + 0.5 * self.p[1].value(direction)
+    }
+    fn generate(&self) -> Vec3 {
+        if sample_1d() < 0.5 {
+            self.p[0].generate()
+        } else {
+            self.p[1].generate()
+        }
+    }
+}
+
+// A direction about the z axis with density cos(theta) / pi.
+fn random_cosine_direction() -> Vec3 {
+    let (r1, r2) = sample_2d();
+    let z = (1.0 - r2).sqrt();
+    let phi = 2.0 * PI * r1;
+    let x = phi.cos() * r2.sqrt();
+    let y = phi.sin() * r2.sqrt();
+    Vec3::construct(&[x, y, z])
+}
@@ -0,0 +1,565 @@
+use crate::camera::Camera;
+use crate::film::{Film, Filter, TileFilm};
+use crate::hittable::{HitRecord, Hittable};
+use crate::hittable_list::HittableList;
+use crate::material::ScatterRecord;
+use crate::pdf::{HittablePdf, MixturePdf, Pdf};
+use crate::ray::Ray;
+use crate::rt_weekend::{random_double, seed_rng};
+use crate::sampler::{sample_2d, set_sampler, start_sample, SamplerKind};
+use crate::vec3::{luminance, Color3};
+use indicatif::ProgressBar;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Veach's power heuristic: the weight of a sample drawn with density `pdf_a`
+// when a second strategy with density `pdf_b` could have drawn it as well.
+fn power_heuristic(pdf_a: f64, pdf_b: f64) -> f64 {
+    let a2 = pdf_a * pdf_a;
+    let b2 = pdf_b * pdf_b;
+    if a2 + b2 <= 0.0 {
+        return 0.0;
+    }
+    a2 / (a2 + b2)
+}
+
+// Direct light at `rec` from a point picked on one of the lights, with a
+// shadow ray to check that nothing is in between. `scatter_pdf` is the other
+// strategy, the one that picks the continuation of the path.
+fn sample_lights(
+    r: &Ray,
+    rec: &HitRecord,
+    world: &dyn Hittable,
+    lights: &HittableList,
+    light_pdf: &HittablePdf,
+    scatter_pdf: &dyn Pdf,
+    attenuation: &Color3,
+) -> Color3 {
+    let direction = light_pdf.generate();
+    let pdf = light_pdf.value(&direction);
+    if pdf <= 0.0 {
+        return Color3::new();
+    }
+    let shadow_ray = Ray::construct(&rec.p, &direction, r.time());
+    let material = rec.mat_ptr.as_ref().unwrap();
+    let scattering_pdf = material.scattering_pdf(r, rec, &shadow_ray);
+    if scattering_pdf <= 0.0 {
+        return Color3::new();
+    }
+
+    // The light the sample reaches, then whether anything in the world is in
+    // front of it. The light itself is in the world too, hence the margin.
+    let mut light_rec = HitRecord::new();
+    if !lights.hit(&shadow_ray, 0.001, f64::INFINITY, &mut light_rec)
+        || world.occluded(&shadow_ray, 0.001, light_rec.t * (1.0 - 1e-4))
+    {
+        return Color3::new();
+    }
+    let emitted =
+        light_rec
+            .mat_ptr
+            .as_ref()
+            .unwrap()
+            .emitted(light_rec.u, light_rec.v, &light_rec.p);
+    let weight = power_heuristic(pdf, scatter_pdf.value(&direction));
+    *attenuation * emitted * (scattering_pdf * weight / pdf)
+}
+
+// Path tracing with next-event estimation: at every diffuse bounce one of the
+// `lights` is sampled directly, and combined with the light that the bounce
+// itself runs into by multiple importance sampling. With no lights this is a
+// path tracer that samples the materials only.
+//
+// A path has at most `max_depth` bounces. From `roulette_depth` on it ends at
+// random, the less likely the more it can still carry, and when it goes on its
+// throughput is divided by the chance that it did, so the mean stays the same.
+pub fn ray_color(
+    r: &Ray,
+    background: &Color3,
+    world: &dyn Hittable,
+    lights: &HittableList,
+    max_depth: i32,
+    roulette_depth: i32,
+) -> PathRadiance {
+    let mut radiance = PathRadiance::default();
+    // What the path still carries to the camera.
+    let mut throughput = Color3::construct(&[1.0, 1.0, 1.0]);
+    let mut ray = *r;
+    // The density with which the previous bounce picked `ray`, or `None` when
+    // light sampling could not have found the same path (camera rays and
+    // specular bounces), in which case emission counts in full.
+    let mut pdf: Option<f64> = None;
+
+    for depth in 0..max_depth {
+        let mut rec: HitRecord = HitRecord::new();
+        if !world.hit(&ray, 0.001, f64::INFINITY, &mut rec) {
+            radiance.add(depth <= 1, throughput * *background);
+            break;
+        }
+
+        let mut srec = ScatterRecord::new();
+        let material = rec.mat_ptr.as_ref().unwrap();
+        let mut emitted = material.emitted(rec.u, rec.v, &rec.p);
+        if let Some(pdf) = pdf {
+            if !emitted.near_zero() {
+                let light_pdf = lights.pdf_value(&ray.origin(), &ray.direction());
+                emitted *= power_heuristic(pdf, light_pdf);
+            }
+        }
+        radiance.add(depth <= 1, throughput * emitted);
+
+        if !material.scatter(&ray, &rec, &mut srec) {
+            break;
+        }
+        if srec.is_specular {
+            throughput = throughput * srec.attenuation;
+            ray = srec.specular_ray;
+            pdf = None;
+        } else {
+            // The path goes on in a direction drawn half of the time towards
+            // the lights and half of the time from the material.
+            let material_pdf = srec.pdf_ptr.as_ref().unwrap().as_ref();
+            let light_pdf = HittablePdf::construct(lights, &rec.p);
+            let mixture_pdf;
+            let scatter_pdf: &dyn Pdf = if lights.objects.is_empty() {
+                material_pdf
+            } else {
+                mixture_pdf = MixturePdf::construct(&light_pdf, material_pdf);
+                let direct = sample_lights(
+                    &ray,
+                    &rec,
+                    world,
+                    lights,
+                    &light_pdf,
+                    &mixture_pdf,
+                    &srec.attenuation,
+                );
+                radiance.add(depth == 0, throughput * direct);
+                &mixture_pdf
+            };
+
+            let scattered = Ray::construct(&rec.p, &scatter_pdf.generate(), ray.time());
+            let scattered_pdf = scatter_pdf.value(&scattered.direction());
+            if scattered_pdf <= 0.0 {
+                break;
+            }
+            let scattering_pdf = material.scattering_pdf(&ray, &rec, &scattered);
+            throughput = throughput * srec.attenuation * (scattering_pdf / scattered_pdf);
+            ray = scattered;
+            pdf = Some(scattered_pdf);
+        }
+
+        // Russian roulette. It draws from the generator rather than the
+        // sampler, as only some paths get this far.
+        if depth + 1 >= roulette_depth {
+            let survival = throughput.x().max(throughput.y()).max(throughput.z());
+            if survival < 1.0 {
+                if random_double() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+        }
+    }
+    radiance
+}
+
+// The light a path brings to the camera. Light that reached the first surface
+// the camera sees, whether found by sampling the lights or by the path itself,
+// is direct, and the rest indirect.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PathRadiance {
+    pub direct: Color3,
+    pub indirect: Color3,
+}
+
+impl PathRadiance {
+    fn add(&mut self, direct: bool, light: Color3) {
+        if direct {
+            self.direct += light;
+        } else {
+            self.indirect += light;
+        }
+    }
+}
+
+// Samples left out for being NaN or infinite, and samples scaled down to the
+// luminance clamps.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SampleCounts {
+    pub nan: u64,
+    pub infinite: u64,
+    pub clamped: u64,
+}
+
+impl SampleCounts {
+    fn add(&mut self, other: &SampleCounts) {
+        self.nan += other.nan;
+        self.infinite += other.infinite;
+        self.clamped += other.clamped;
+    }
+}
+
+// `color` scaled down to luminance `max` if it is brighter, with its hue kept.
+fn clamp_luminance(color: Color3, max: f64) -> Option<Color3> {
+    let l = luminance(&color);
+    if l <= max {
+        return None;
+    }
+    Some(color * (max / l))
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RenderSettings {
+    pub image_width: u32,
+    pub image_height: u32,
+    pub samples_per_pixel: u32,
+    // Samples added to every pixel by each pass over the image.
+    pub pass_samples: u32,
+    // Stops after the first pass that ends past this, short of
+    // `samples_per_pixel` if need be.
+    pub time_limit: Option<Duration>,
+    // Adaptive sampling: `samples_per_pixel` is the mean over the image. A
+    // pixel with `min_samples` samples stops once the relative error of its
+    // mean is below `max_error`, and what it saves goes to the noisier ones,
+    // up to `max_samples` each.
+    pub min_samples: u32,
+    pub max_samples: u32,
+    pub max_error: Option<f64>,
+    pub sampler: SamplerKind,
+    pub filter: Filter,
+    pub max_depth: i32,
+    // Bounces a path always makes before Russian roulette may end it.
+    pub roulette_depth: i32,
+    // The most luminance a sample may have, and its indirect light on its own.
+    // Brighter ones are scaled down, which takes out fireflies at the cost of
+    // some energy.
+    pub clamp: Option<f64>,
+    pub clamp_indirect: Option<f64>,
+    pub thread_num: u32,
+    pub tile_size: u32,
+    pub seed: u64,
+}
+
+// A rectangle of the image, `x0..x1` by `y0..y1` in image rows (top row is 0).
+#[derive(Clone, Copy, Debug)]
+pub struct Tile {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl Tile {
+    pub fn split(image_width: u32, image_height: u32, tile_size: u32) -> Vec<Tile> {
+        let mut tiles = Vec::new();
+        for y0 in (0..image_height).step_by(tile_size as usize) {
+            for x0 in (0..image_width).step_by(tile_size as usize) {
+                tiles.push(Tile {
+                    x0,
+                    y0,
+                    x1: (x0 + tile_size).min(image_width),
+                    y1: (y0 + tile_size).min(image_height),
+                });
+            }
+        }
+        tiles
+    }
+}
+
+// The indices of the samples every pixel takes in the next pass.
+fn pass_budget(film: &Film, settings: &RenderSettings) -> Vec<Range<u32>> {
+    let max_error = match settings.max_error {
+        Some(max_error) => max_error,
+        None => {
+            return film
+                .samples
+                .iter()
+                .map(|n| {
+                    let count = settings
+                        .pass_samples
+                        .min(settings.samples_per_pixel.saturating_sub(*n));
+                    *n..*n + count
+                })
+                .collect()
+        }
+    };
+
+    // The samples left of the whole image's budget go to the pixels short of
+    // `min_samples` first, then to the noisiest.
+    let total = settings.samples_per_pixel as u64 * film.samples.len() as u64;
+    let spent: u64 = film.samples.iter().map(|n| *n as u64).sum();
+    let mut left = total.saturating_sub(spent);
+    let mut active: Vec<(usize, bool, f64)> = (0..film.samples.len())
+        .filter_map(|index| {
+            let n = film.samples[index];
+            let error = film.relative_error(index);
+            let converged = n >= settings.min_samples && error <= max_error;
+            if converged || n >= settings.max_samples {
+                None
+            } else {
+                Some((index, n >= settings.min_samples, error))
+            }
+        })
+        .collect();
+    active.sort_by(|a, b| {
+        a.1.cmp(&b.1)
+            .then(b.2.partial_cmp(&a.2).unwrap_or(std::cmp::Ordering::Equal))
+    });
+
+    let mut budget: Vec<Range<u32>> = film.samples.iter().map(|n| *n..*n).collect();
+    for (index, _, _) in active {
+        let n = film.samples[index];
+        let count = settings
+            .pass_samples
+            .min(settings.max_samples - n)
+            .min(left.min(u32::MAX as u64) as u32);
+        if count == 0 {
+            break;
+        }
+        budget[index] = n..n + count;
+        left -= count as u64;
+    }
+    budget
+}
+
+// Where a render stands after one of its passes.
+#[derive(Clone, Copy, Debug)]
+pub struct PassReport {
+    pub pass: u32,
+    // The most samples any pixel has, and how many they have on average.
+    pub samples_per_pixel: u32,
+    pub mean_samples: f64,
+    // Pixels that take samples in the next pass.
+    pub active_pixels: usize,
+    pub elapsed: Duration,
+    // Over all the passes of this render, not counting any it resumed from.
+    pub counts: SampleCounts,
+    pub out_of_time: bool,
+    // No pass follows this one.
+    pub last: bool,
+}
+
+// What a render draws, shared by its workers.
+#[derive(Clone)]
+pub struct RenderScene {
+    pub world: Arc<HittableList>,
+    pub lights: Arc<HittableList>,
+    pub cam: Camera,
+    pub background: Color3,
+}
+
+// The samples `budget` asks of each pixel of the tile, splatted through the
+// filter.
+fn render_tile(
+    tile: &Tile,
+    scene: &RenderScene,
+    settings: &RenderSettings,
+    pass: u32,
+    budget: &[Range<u32>],
+) -> (TileFilm, SampleCounts) {
+    let mut counts = SampleCounts::default();
+    let width = settings.image_width;
+    let height = settings.image_height;
+    let mut film = TileFilm::construct(tile, settings.filter, width, height);
+    for y in tile.y0..tile.y1 {
+        for x in tile.x0..tile.x1 {
+            // Every pass draws from streams of its own, the first one from
+            // the pixel index alone.
+            let pixel = (y * width + x) as u64;
+            seed_rng(settings.seed, (pass as u64) << 32 | pixel);
+            for index in budget[pixel as usize].clone() {
+                start_sample(pixel, index);
+                // Anywhere in the pixel, which spans [x, x + 1) of the film.
+                // Camera rows count upwards from the bottom of the image.
+                let (dx, dy) = sample_2d();
+                let px = x as f64 + dx;
+                let py = y as f64 + dy;
+                let r: Ray = scene
+                    .cam
+                    .get_ray(px / width as f64, 1.0 - py / height as f64);
+                let radiance = ray_color(
+                    &r,
+                    &scene.background,
+                    scene.world.as_ref(),
+                    &scene.lights,
+                    settings.max_depth,
+                    settings.roulette_depth,
+                );
+                match sanitize(&radiance, settings, &mut counts) {
+                    Some(color) => film.add_sample(x, y, px, py, &color),
+                    None => film.skip_sample(x, y),
+                }
+            }
+        }
+    }
+    (film, counts)
+}
+
+// What a sample adds to its pixel: its light within the clamps, or nothing if
+// it is not a number or infinite. Either is counted once per sample.
+fn sanitize(
+    radiance: &PathRadiance,
+    settings: &RenderSettings,
+    counts: &mut SampleCounts,
+) -> Option<Color3> {
+    let mut color = radiance.direct + radiance.indirect;
+    if color.e.iter().any(|c| c.is_nan()) {
+        counts.nan += 1;
+        return None;
+    }
+    if color.e.iter().any(|c| c.is_infinite()) {
+        counts.infinite += 1;
+        return None;
+    }
+    let mut clamped = false;
+    if let Some(max) = settings.clamp_indirect {
+        if let Some(indirect) = clamp_luminance(radiance.indirect, max) {
+            color = radiance.direct + indirect;
+            clamped = true;
+        }
+    }
+    if let Some(max) = settings.clamp {
+        if let Some(c) = clamp_luminance(color, max) {
+            color = c;
+            clamped = true;
+        }
+    }
+    if clamped {
+        counts.clamped += 1;
+    }
+    Some(color)
+}
+
+// The film, and the tiles of the current pass that are done but wait for the
+// ones before them to be merged first.
+struct Accumulator {
+    film: Film,
+    counts: SampleCounts,
+    done: Vec<Option<TileFilm>>,
+    next: usize,
+}
+
+impl Accumulator {
+    fn start_pass(&mut self, tiles: usize) {
+        self.done = (0..tiles).map(|_| None).collect();
+        self.next = 0;
+    }
+    fn add(&mut self, index: usize, tile: TileFilm, counts: &SampleCounts) {
+        self.counts.add(counts);
+        self.done[index] = Some(tile);
+        while let Some(tile) = self.done.get_mut(self.next).and_then(Option::take) {
+            self.film.merge(tile);
+            self.next += 1;
+        }
+    }
+}
+
+// Renders the image in passes of `pass_samples` samples per pixel over the
+// whole image, until it has `samples_per_pixel` of them or the time limit is
+// up. An adaptive render leaves out the pixels that have converged. `on_pass`
+// sees the film after every pass, to write previews and checkpoints. `film`
+// may hold earlier passes, which the render goes on from.
+//
+// A pass runs on a fixed pool of workers that take tiles off a shared queue.
+// Every pixel reseeds the generator and the tiles are merged into the film in
+// order, so for a given seed the result does not depend on the thread count or
+// on which thread rendered which tile.
+pub fn render(
+    scene: &RenderScene,
+    settings: &RenderSettings,
+    film: Film,
+    progress: &ProgressBar,
+    mut on_pass: impl FnMut(&Film, &PassReport),
+) -> Film {
+    let start = Instant::now();
+    let tiles = Arc::new(Tile::split(
+        settings.image_width,
+        settings.image_height,
+        settings.tile_size,
+    ));
+    let fewest = film.samples.iter().copied().min().unwrap_or(0);
+    let remaining = settings.max_samples.saturating_sub(fewest);
+    // In 64 bits, as the sample counts can be as large as any u32.
+    let pass_samples = settings.pass_samples as u64;
+    let passes = (remaining as u64 + pass_samples - 1) / pass_samples;
+    progress.set_length(tiles.len() as u64 * passes);
+    let mut budget = Arc::new(pass_budget(&film, settings));
+    let accumulator = Arc::new(Mutex::new(Accumulator {
+        film,
+        counts: SampleCounts::default(),
+        done: Vec::new(),
+        next: 0,
+    }));
+
+    while budget.iter().any(|samples| !samples.is_empty()) {
+        let pass = {
+            let mut accumulator = accumulator.lock().unwrap();
+            accumulator.start_pass(tiles.len());
+            accumulator.film.passes
+        };
+        let next_tile = Arc::new(AtomicUsize::new(0));
+        let mut handles: Vec<thread::JoinHandle<()>> = Vec::new();
+        for _t in 0..settings.thread_num {
+            let tiles = tiles.clone();
+            let next_tile = next_tile.clone();
+            let accumulator = accumulator.clone();
+            let scene = scene.clone();
+            let settings = *settings;
+            let budget = budget.clone();
+            let progress = progress.clone();
+
+            handles.push(thread::spawn(move || {
+                set_sampler(
+                    settings
+                        .sampler
+                        .construct(settings.seed, settings.max_samples),
+                );
+                loop {
+                    let index = next_tile.fetch_add(1, Ordering::Relaxed);
+                    if index >= tiles.len() {
+                        break;
+                    }
+                    let (tile, counts) =
+                        render_tile(&tiles[index], &scene, &settings, pass, &budget);
+                    accumulator.lock().unwrap().add(index, tile, &counts);
+                    progress.inc(1);
+                }
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let mut accumulator = accumulator.lock().unwrap();
+        let counts = accumulator.counts;
+        let film = &mut accumulator.film;
+        film.passes = pass + 1;
+        budget = Arc::new(pass_budget(film, settings));
+        let elapsed = start.elapsed();
+        let out_of_time = matches!(settings.time_limit, Some(limit) if elapsed >= limit);
+        let active_pixels = budget.iter().filter(|samples| !samples.is_empty()).count();
+        let report = PassReport {
+            pass,
+            samples_per_pixel: film.samples.iter().copied().max().unwrap_or(0),
+            mean_samples: film.mean_samples(),
+            active_pixels,
+            elapsed,
+            counts,
+            out_of_time,
+            last: out_of_time || active_pixels == 0,
+        };
+        on_pass(film, &report);
+        if report.last {
+            break;
+        }
+    }
+
+    match Arc::try_unwrap(accumulator) {
+        Ok(accumulator) => accumulator.into_inner().unwrap().film,
+        Err(_) => panic!("render: a worker still holds the film"),
+    }
+}
@@ -1,3 +1,5 @@
+use std::cell::RefCell;
+
 pub const INFINITY: f64 = std::f64::INFINITY;
 pub const PI: f64 = std::f64::consts::PI;
 
@@ -5,17 +7,73 @@ pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * PI / 180.0
 }
 
+// PCG32 (XSH-RR variant), see https://www.pcg-random.org.
+#[derive(Clone, Copy, Debug)]
+pub struct Pcg32 {
+    state: u64,
+    inc: u64,
+}
+
+impl Pcg32 {
+    const MULTIPLIER: u64 = 6364136223846793005;
+
+    pub fn construct(seed: u64, stream: u64) -> Self {
+        let mut rng = Self {
+            state: 0,
+            inc: (stream << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(Self::MULTIPLIER).wrapping_add(self.inc);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+    pub fn next_f64(&mut self) -> f64 {
+        // 53 random bits, the full precision of an f64 in [0,1).
+        let bits = ((self.next_u32() as u64) << 21) ^ (self.next_u32() as u64);
+        (bits & ((1 << 53) - 1)) as f64 * (1.0 / (1u64 << 53) as f64)
+    }
+}
+
+// Mixes a 64 bit value, used to turn (seed, index) pairs into PCG streams.
+pub fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+thread_local! {
+    static RNG: RefCell<Pcg32> = RefCell::new(Pcg32::construct(0, 0));
+}
+
+// Restarts the calling thread's generator. Everything that draws random
+// numbers (camera, materials, media, textures, BVH construction) goes through
+// this generator, so the renderer reseeds it for every pixel with the pixel
+// index as `key`; a pixel then gets the same samples no matter which thread
+// renders it.
+pub fn seed_rng(seed: u64, key: u64) {
+    let hashed = splitmix64(seed ^ splitmix64(key));
+    RNG.with(|rng| *rng.borrow_mut() = Pcg32::construct(hashed, splitmix64(hashed)));
+}
+
 pub fn random_double() -> f64 {
     // Returns a random real in [0,1).
-    rand::random::<f64>()
+    RNG.with(|rng| rng.borrow_mut().next_f64())
 }
 
 pub fn random_double_range(min: f64, max: f64) -> f64 {
-    min + (max - min) * rand::random::<f64>()
+    min + (max - min) * random_double()
 }
 pub fn random_int(min: u32, max: u32) -> u32 {
     // Returns a random integer in [min,max].
-    min + (rand::random::<f64>() * (max - min + 1) as f64).floor() as u32
+    min + (random_double() * (max - min + 1) as f64).floor() as u32
 }
 
 pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
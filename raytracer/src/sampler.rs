@@ -0,0 +1,372 @@
+// Where the samples of the integrand come from. Every pixel sample is a point
+// in a space of many dimensions: the camera, the materials and the lights take
+// their 1D and 2D coordinates from it, in the order they ask for them. Samplers
+// that spread these points evenly (stratified, Halton, Sobol) give less noise
+// than independent random numbers at the same sample count.
+use crate::rt_weekend::{random_double, splitmix64};
+use std::cell::RefCell;
+use std::fmt;
+
+pub trait Sampler {
+    // Starts sample `index` of `pixel`, at its first dimension.
+    fn start_sample(&mut self, pixel: u64, index: u32);
+    fn get_1d(&mut self) -> f64;
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SamplerKind {
+    Random,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl SamplerKind {
+    pub const NAMES: [&'static str; 4] = ["random", "stratified", "halton", "sobol"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "random" => Some(Self::Random),
+            "stratified" => Some(Self::Stratified),
+            "halton" => Some(Self::Halton),
+            "sobol" => Some(Self::Sobol),
+            _ => None,
+        }
+    }
+    // `samples_per_pixel` is the most samples a pixel takes, which the
+    // stratified sampler divides its dimensions into.
+    pub fn construct(self, seed: u64, samples_per_pixel: u32) -> Box<dyn Sampler> {
+        let state = SampleState::construct(seed);
+        match self {
+            Self::Random => Box::new(IndependentSampler),
+            Self::Stratified => Box::new(StratifiedSampler {
+                state,
+                samples_per_pixel,
+            }),
+            Self::Halton => Box::new(HaltonSampler { state }),
+            Self::Sobol => Box::new(SobolSampler { state }),
+        }
+    }
+}
+
+impl fmt::Display for SamplerKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Random => write!(f, "random"),
+            Self::Stratified => write!(f, "stratified"),
+            Self::Halton => write!(f, "halton"),
+            Self::Sobol => write!(f, "sobol"),
+        }
+    }
+}
+
+// Independent uniform numbers from the thread's generator.
+pub struct IndependentSampler;
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, _pixel: u64, _index: u32) {}
+    fn get_1d(&mut self) -> f64 {
+        random_double()
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        (random_double(), random_double())
+    }
+}
+
+// The sample being taken, and a hash for each of its dimensions that
+// decorrelates pixels and dimensions from one another.
+struct SampleState {
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dimension: u32,
+}
+
+impl SampleState {
+    fn construct(seed: u64) -> Self {
+        Self {
+            seed,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+    fn start(&mut self, pixel: u64, index: u32) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = 0;
+    }
+    // Moves on by `count` dimensions, returning the first one and its hash.
+    fn next(&mut self, count: u32) -> (u32, u64) {
+        let dimension = self.dimension;
+        self.dimension += count;
+        let hash = splitmix64(self.seed ^ splitmix64(self.pixel ^ splitmix64(dimension as u64)));
+        (dimension, hash)
+    }
+}
+
+// Kensler's hashed permutation: element `i` of a random permutation of
+// `0..len` picked by `p`.
+fn permutation_element(mut i: u32, len: u32, p: u32) -> u32 {
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % len
+}
+
+// A point `offset` of the way through stratum `stratum` of `count`. Near the
+// top of the last stratum the sum rounds up to 1, which is kept out.
+fn jitter(stratum: u32, offset: f64, count: u32) -> f64 {
+    ((stratum as f64 + offset) / count as f64).min(1.0 - f64::EPSILON)
+}
+
+// Jittered samples: every dimension is cut into one stratum per sample (or
+// a grid of about that many cells, in 2D), and each sample of a pixel lands in
+// its own stratum, in an order shuffled per pixel and dimension.
+pub struct StratifiedSampler {
+    state: SampleState,
+    samples_per_pixel: u32,
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, pixel: u64, index: u32) {
+        self.state.start(pixel, index);
+    }
+    fn get_1d(&mut self) -> f64 {
+        let (_, hash) = self.state.next(1);
+        let count = self.samples_per_pixel;
+        let stratum = permutation_element(self.state.index % count, count, hash as u32);
+        jitter(stratum, random_double(), count)
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (_, hash) = self.state.next(2);
+        let count = self.samples_per_pixel;
+        // Small enough for the number of cells to fit in a u32.
+        let n = ((count as f64).sqrt().ceil() as u32).min(u16::MAX as u32);
+        let cell = permutation_element(self.state.index % count, n * n, hash as u32);
+        (
+            jitter(cell % n, random_double(), n),
+            jitter(cell / n, random_double(), n),
+        )
+    }
+}
+
+// Bases of the Halton dimensions. Dimensions past them are independent.
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+// The digits of `index` in `base`, mirrored about the radix point, with each
+// digit permuted depending on the digits before it (Owen scrambling). Trailing
+// zeros are scrambled too, down to about 32 bits of precision.
+fn scrambled_radical_inverse(base: u32, mut index: u32, hash: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut weight = 1.0;
+    let mut reversed: u64 = 0;
+    while weight > 1.0 / (1u64 << 32) as f64 {
+        let digit = index % base;
+        index /= base;
+        let digit_hash = splitmix64(hash ^ reversed) as u32;
+        reversed = reversed * base as u64 + permutation_element(digit, base, digit_hash) as u64;
+        weight *= inv_base;
+    }
+    (reversed as f64 * weight).min(1.0 - f64::EPSILON)
+}
+
+// The Halton sequence, Owen scrambled per pixel and dimension. Unscrambled, the
+// first samples in a large base all crowd near zero, and every pixel would
+// repeat the same points.
+pub struct HaltonSampler {
+    state: SampleState,
+}
+
+impl HaltonSampler {
+    fn dimension(&self, dimension: u32, hash: u64) -> f64 {
+        match PRIMES.get(dimension as usize) {
+            Some(base) => scrambled_radical_inverse(*base, self.state.index, hash),
+            None => random_double(),
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, pixel: u64, index: u32) {
+        self.state.start(pixel, index);
+    }
+    fn get_1d(&mut self) -> f64 {
+        let (dimension, hash) = self.state.next(1);
+        self.dimension(dimension, hash)
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (dimension, hash) = self.state.next(2);
+        (
+            self.dimension(dimension, hash),
+            self.dimension(dimension + 1, splitmix64(hash)),
+        )
+    }
+}
+
+// Burley's hash-based Owen scrambling: flips every bit of `v` depending on the
+// bits above it, the same way for the same `seed`.
+fn owen_scramble(v: u32, seed: u32) -> u32 {
+    let mut v = v.reverse_bits();
+    v ^= v.wrapping_mul(0x3d20adea);
+    v = v.wrapping_add(seed);
+    v = v.wrapping_mul((seed >> 16) | 1);
+    v ^= v.wrapping_mul(0x05526c56);
+    v ^= v.wrapping_mul(0x53a22864);
+    v.reverse_bits()
+}
+
+// The second dimension of the Sobol sequence, as a 32 bit fraction. The first
+// one is just the bits of the index reversed.
+fn sobol_second(mut index: u32) -> u32 {
+    let mut direction = 1u32 << 31;
+    let mut result = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+fn fraction_to_unit(v: u32) -> f64 {
+    v as f64 * (1.0 / (1u64 << 32) as f64)
+}
+
+// Padded Sobol: every 1D or 2D dimension takes the first one or two Sobol
+// dimensions, Owen scrambled, at an index shuffled per pixel and dimension.
+// The shuffle keeps the first 2^k samples of a pixel a scrambled net, so the
+// points stay well spread however many samples progressive or adaptive
+// rendering ends up taking.
+pub struct SobolSampler {
+    state: SampleState,
+}
+
+impl SobolSampler {
+    fn shuffled_index(&self, hash: u64) -> u32 {
+        owen_scramble(self.state.index, hash as u32)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, pixel: u64, index: u32) {
+        self.state.start(pixel, index);
+    }
+    fn get_1d(&mut self) -> f64 {
+        let (_, hash) = self.state.next(1);
+        let index = self.shuffled_index(hash);
+        fraction_to_unit(owen_scramble(index.reverse_bits(), (hash >> 32) as u32))
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (_, hash) = self.state.next(2);
+        let index = self.shuffled_index(hash);
+        let seed = splitmix64(hash);
+        (
+            fraction_to_unit(owen_scramble(index.reverse_bits(), (hash >> 32) as u32)),
+            fraction_to_unit(owen_scramble(sobol_second(index), seed as u32)),
+        )
+    }
+}
+
+thread_local! {
+    static SAMPLER: RefCell<Box<dyn Sampler>> = RefCell::new(Box::new(IndependentSampler));
+}
+
+// Makes `sampler` the one the calling thread draws samples from.
+pub fn set_sampler(sampler: Box<dyn Sampler>) {
+    SAMPLER.with(|s| *s.borrow_mut() = sampler);
+}
+
+pub fn start_sample(pixel: u64, index: u32) {
+    SAMPLER.with(|s| s.borrow_mut().start_sample(pixel, index));
+}
+
+// The next dimension of the current sample, in [0, 1).
+pub fn sample_1d() -> f64 {
+    SAMPLER.with(|s| s.borrow_mut().get_1d())
+}
+
+// The next two dimensions of the current sample, in [0, 1)^2.
+pub fn sample_2d() -> (f64, f64) {
+    SAMPLER.with(|s| s.borrow_mut().get_2d())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_are_in_the_unit_interval() {
+        let in_range = |v: f64| (0.0..1.0).contains(&v);
+        for kind in SamplerKind::NAMES
+            .iter()
+            .map(|n| SamplerKind::from_name(n).unwrap())
+        {
+            for spp in [1, 7, 16, 1000, u32::MAX] {
+                let mut sampler = kind.construct(42, spp);
+                for pixel in [0, 1, 12345, u64::MAX] {
+                    for index in (0..spp.min(200)).chain([spp - 1, u32::MAX]) {
+                        sampler.start_sample(pixel, index);
+                        for _dimension in 0..40 {
+                            let v = sampler.get_1d();
+                            assert!(in_range(v), "{} gave {}", kind, v);
+                            let (u, v) = sampler.get_2d();
+                            assert!(in_range(u) && in_range(v), "{} gave ({}, {})", kind, u, v);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn top_stratum_stays_below_one() {
+        // The largest value `random_double` can return.
+        let top = 1.0 - f64::EPSILON / 2.0;
+        assert_eq!(2.0 + top, 3.0);
+        for count in 1..=1024 {
+            let v = jitter(count - 1, top, count);
+            assert!(
+                v < 1.0 && v > (count - 1) as f64 / count as f64,
+                "{} gave {}",
+                count,
+                v
+            );
+            assert_eq!(jitter(0, 0.0, count), 0.0);
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use crate::camera::Camera;
+use crate::hittable::Hittable;
+use crate::hittable_list::HittableList;
+use crate::vec3::{Color3, Point3, Vec3};
+use std::path::PathBuf;
+
+// Everything needed to render a picture besides the image settings.
+pub struct Scene {
+    pub world: HittableList,
+    pub background: Color3,
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    pub vup: Vec3,
+    pub vfov: f64,
+    pub aperture: f64,
+    pub focus_dist: f64,
+    pub time0: f64,
+    pub time1: f64,
+    // The models loaded from files, with a summary of each.
+    pub meshes: Vec<(PathBuf, String)>,
+    // The files the scene was read from, whose contents make it what it is.
+    pub sources: Vec<PathBuf>,
+}
+
+impl Scene {
+    pub fn construct(
+        world: HittableList,
+        background: &Color3,
+        lookfrom: &Point3,
+        lookat: &Point3,
+        vfov: f64,
+    ) -> Self {
+        Self {
+            world,
+            background: *background,
+            lookfrom: *lookfrom,
+            lookat: *lookat,
+            vup: Vec3::construct(&[0.0, 1.0, 0.0]),
+            vfov,
+            aperture: 0.0,
+            focus_dist: 10.0,
+            time0: 0.0,
+            time1: 1.0,
+            meshes: Vec::new(),
+            sources: Vec::new(),
+        }
+    }
+    pub fn camera(&self, aspect_ratio: f64) -> Camera {
+        Camera::new(
+            &self.lookfrom,
+            &self.lookat,
+            &self.vup,
+            &[self.vfov, aspect_ratio, self.aperture, self.focus_dist],
+            self.time0,
+            self.time1,
+        )
+    }
+    // The emissive objects of the world that can be sampled directly.
+    pub fn lights(&self) -> HittableList {
+        let mut lights = HittableList::new();
+        self.world.collect_lights(&mut lights);
+        lights
+    }
+}
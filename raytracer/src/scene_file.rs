@@ -0,0 +1,813 @@
+// Loads a scene from a JSON description, see `scenes/` for examples.
+//
+// Textures and materials can be declared once under `textures` / `materials`
+// and referenced by name, or written inline wherever they are used.
+// Wherever a texture is expected a plain `[r, g, b]` color works as well.
+use crate::aarect::{XyRect, XzRect, YzRect};
+use crate::boxes::Box_;
+use crate::bvh::BVHNode;
+use crate::constant_medium::ConstantMedium;
+use crate::hittable::Hittable;
+use crate::hittable_list::HittableList;
+use crate::json::{self, Json, JsonValue, Pos};
+use crate::material::{Dielectric, DiffuseLight, Isotropic, Lambertian, Material, Metal};
+use crate::moving_sphere::MovingSphere;
+use crate::obj_loader::{load_obj, ObjOptions};
+use crate::scene::Scene;
+use crate::sphere::Sphere;
+use crate::texture::{CheckerTexture, ImageTexture, NoiseTexture, SolidColor, Texture};
+use crate::transform::{Instance, Keyframe, Motion, MovingInstance, Quaternion, Transform};
+use crate::triangle::Triangle;
+use crate::vec3::{cross, Color3, Vec3};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct SceneError {
+    pub file: String,
+    pub pos: Option<Pos>,
+    pub message: String,
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.pos {
+            Some(pos) => write!(f, "{}:{}: {}", self.file, pos, self.message),
+            None => write!(f, "{}: {}", self.file, self.message),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, SceneError>;
+
+// Every object accepts `type`, `transforms` and `motion` on top of its own
+// fields.
+fn with_common(fields: &[&'static str]) -> Vec<&'static str> {
+    let mut all = vec!["type", "transforms", "motion"];
+    all.extend_from_slice(fields);
+    all
+}
+
+pub fn load_scene(path: &Path) -> Result<Scene> {
+    let file = path.display().to_string();
+    let src = std::fs::read_to_string(path).map_err(|e| SceneError {
+        file: file.clone(),
+        pos: None,
+        message: e.to_string(),
+    })?;
+    let root = json::parse(&src).map_err(|e| SceneError {
+        file: file.clone(),
+        pos: Some(e.pos),
+        message: e.message,
+    })?;
+    let mut loader = Loader {
+        file,
+        dir: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        shapes: HashMap::new(),
+        meshes: RefCell::new(Vec::new()),
+        sources: RefCell::new(vec![path.to_path_buf()]),
+    };
+    loader.scene(&root)
+}
+
+struct Loader {
+    file: String,
+    // Relative texture paths are resolved against the scene file's directory.
+    dir: PathBuf,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    materials: HashMap<String, Arc<dyn Material>>,
+    shapes: HashMap<String, Arc<dyn Hittable>>,
+    meshes: RefCell<Vec<(PathBuf, String)>>,
+    // Every file read so far, the scene file first.
+    sources: RefCell<Vec<PathBuf>>,
+}
+
+impl Loader {
+    fn error<T>(&self, pos: Pos, message: String) -> Result<T> {
+        Err(SceneError {
+            file: self.file.clone(),
+            pos: Some(pos),
+            message,
+        })
+    }
+
+    fn entries<'j>(&self, v: &'j Json, what: &str) -> Result<&'j [(String, Pos, Json)]> {
+        match &v.value {
+            JsonValue::Object(fields) => Ok(fields),
+            _ => self.error(
+                v.pos,
+                format!("expected {} to be an object, found {}", what, v.type_name()),
+            ),
+        }
+    }
+    // Checks that `v` is an object and that it has no fields besides `allowed`.
+    fn fields<'j>(
+        &self,
+        v: &'j Json,
+        what: &str,
+        allowed: &[&str],
+    ) -> Result<&'j [(String, Pos, Json)]> {
+        let fields = self.entries(v, what)?;
+        for (key, pos, _) in fields {
+            if !allowed.contains(&key.as_str()) {
+                return self.error(
+                    *pos,
+                    format!(
+                        "unknown field `{}` in {}, expected one of: {}",
+                        key,
+                        what,
+                        allowed.join(", ")
+                    ),
+                );
+            }
+        }
+        Ok(fields)
+    }
+    fn required<'j>(&self, v: &'j Json, key: &str, what: &str) -> Result<&'j Json> {
+        match v.get(key) {
+            Some(field) => Ok(field),
+            None => self.error(v.pos, format!("missing field `{}` in {}", key, what)),
+        }
+    }
+
+    fn number(&self, v: &Json) -> Result<f64> {
+        match v.value {
+            JsonValue::Number(x) => Ok(x),
+            _ => self.error(v.pos, format!("expected a number, found {}", v.type_name())),
+        }
+    }
+    fn positive(&self, v: &Json) -> Result<f64> {
+        let x = self.number(v)?;
+        if x <= 0.0 {
+            return self.error(v.pos, format!("expected a positive number, found {}", x));
+        }
+        Ok(x)
+    }
+    fn number_or(&self, v: &Json, key: &str, default: f64) -> Result<f64> {
+        match v.get(key) {
+            Some(field) => self.number(field),
+            None => Ok(default),
+        }
+    }
+    fn boolean_or(&self, v: &Json, key: &str, default: bool) -> Result<bool> {
+        match v.get(key) {
+            Some(Json {
+                value: JsonValue::Bool(b),
+                ..
+            }) => Ok(*b),
+            Some(field) => self.error(
+                field.pos,
+                format!("expected a boolean, found {}", field.type_name()),
+            ),
+            None => Ok(default),
+        }
+    }
+    fn string<'j>(&self, v: &'j Json) -> Result<&'j str> {
+        match &v.value {
+            JsonValue::String(s) => Ok(s),
+            _ => self.error(v.pos, format!("expected a string, found {}", v.type_name())),
+        }
+    }
+    fn array<'j>(&self, v: &'j Json) -> Result<&'j [Json]> {
+        match &v.value {
+            JsonValue::Array(items) => Ok(items),
+            _ => self.error(v.pos, format!("expected an array, found {}", v.type_name())),
+        }
+    }
+    fn numbers(&self, v: &Json, n: usize) -> Result<Vec<f64>> {
+        let items = self.array(v)?;
+        if items.len() != n {
+            return self.error(
+                v.pos,
+                format!("expected {} numbers, found {}", n, items.len()),
+            );
+        }
+        items.iter().map(|x| self.number(x)).collect()
+    }
+    fn vec3(&self, v: &Json) -> Result<Vec3> {
+        Ok(Vec3::construct(&self.numbers(v, 3)?))
+    }
+    fn vec3_or(&self, v: &Json, key: &str, default: &Vec3) -> Result<Vec3> {
+        match v.get(key) {
+            Some(field) => self.vec3(field),
+            None => Ok(*default),
+        }
+    }
+    fn kind<'j>(&self, v: &'j Json, what: &str) -> Result<&'j str> {
+        self.string(self.required(v, "type", what)?)
+    }
+    fn path(&self, v: &Json) -> Result<PathBuf> {
+        let path = Path::new(self.string(v)?);
+        Ok(if path.is_relative() {
+            self.dir.join(path)
+        } else {
+            path.to_path_buf()
+        })
+    }
+
+    fn scene(&mut self, root: &Json) -> Result<Scene> {
+        self.fields(
+            root,
+            "the scene",
+            &[
+                "camera",
+                "background",
+                "textures",
+                "materials",
+                "shapes",
+                "objects",
+                "bvh",
+            ],
+        )?;
+
+        if let Some(textures) = root.get("textures") {
+            for (name, _, def) in self.entries(textures, "`textures`")? {
+                let texture = self.texture_def(def)?;
+                self.textures.insert(name.clone(), texture);
+            }
+        }
+        if let Some(materials) = root.get("materials") {
+            for (name, _, def) in self.entries(materials, "`materials`")? {
+                let material = self.material_def(def)?;
+                self.materials.insert(name.clone(), material);
+            }
+        }
+
+        let camera = self.required(root, "camera", "the scene")?;
+        self.fields(
+            camera,
+            "the camera",
+            &[
+                "lookfrom",
+                "lookat",
+                "vup",
+                "vfov",
+                "aperture",
+                "focus_dist",
+                "time0",
+                "time1",
+            ],
+        )?;
+        let time0 = self.number_or(camera, "time0", 0.0)?;
+        let time1 = self.number_or(camera, "time1", 1.0)?;
+
+        // Objects that are only placed through `instance` objects, and built
+        // once however many times they are placed.
+        if let Some(shapes) = root.get("shapes") {
+            for (name, _, def) in self.entries(shapes, "`shapes`")? {
+                let shape = self.object(def, time0, time1)?;
+                self.shapes.insert(name.clone(), shape);
+            }
+        }
+
+        let mut objects = HittableList::new();
+        for item in self.array(self.required(root, "objects", "the scene")?)? {
+            objects.add(self.object(item, time0, time1)?);
+        }
+        let world = if self.boolean_or(root, "bvh", true)? && !objects.objects.is_empty() {
+            HittableList::construct(Arc::new(BVHNode::new(&objects, time0, time1)))
+        } else {
+            objects
+        };
+
+        let background = self.vec3_or(root, "background", &Color3::new())?;
+        let mut scene = Scene::construct(
+            world,
+            &background,
+            &self.vec3(self.required(camera, "lookfrom", "the camera")?)?,
+            &self.vec3(self.required(camera, "lookat", "the camera")?)?,
+            self.number(self.required(camera, "vfov", "the camera")?)?,
+        );
+        scene.vup = self.vec3_or(camera, "vup", &scene.vup)?;
+        scene.aperture = self.number_or(camera, "aperture", scene.aperture)?;
+        if let Some(focus_dist) = camera.get("focus_dist") {
+            scene.focus_dist = self.positive(focus_dist)?;
+        }
+        scene.time0 = time0;
+        scene.time1 = time1;
+        scene.meshes = self.meshes.take();
+        scene.sources = self.sources.take();
+        Ok(scene)
+    }
+
+    fn texture(&self, v: &Json) -> Result<Arc<dyn Texture>> {
+        match &v.value {
+            JsonValue::String(name) => match self.textures.get(name) {
+                Some(texture) => Ok(texture.clone()),
+                None => self.error(v.pos, format!("unknown texture `{}`", name)),
+            },
+            JsonValue::Array(_) => Ok(Arc::new(SolidColor::construct(&self.vec3(v)?))),
+            _ => self.texture_def(v),
+        }
+    }
+
+    fn texture_def(&self, v: &Json) -> Result<Arc<dyn Texture>> {
+        let kind = self.kind(v, "a texture")?;
+        let what = format!("`{}` texture", kind);
+        let texture: Arc<dyn Texture> = match kind {
+            "solid" => {
+                self.fields(v, &what, &["type", "color"])?;
+                Arc::new(SolidColor::construct(
+                    &self.vec3(self.required(v, "color", &what)?)?,
+                ))
+            }
+            "checker" => {
+                self.fields(v, &what, &["type", "even", "odd"])?;
+                Arc::new(CheckerTexture::construct(
+                    self.texture(self.required(v, "even", &what)?)?,
+                    self.texture(self.required(v, "odd", &what)?)?,
+                ))
+            }
+            "noise" => {
+                self.fields(v, &what, &["type", "scale"])?;
+                Arc::new(NoiseTexture::construct(self.number_or(v, "scale", 1.0)?))
+            }
+            "image" => {
+                self.fields(v, &what, &["type", "path"])?;
+                let field = self.required(v, "path", &what)?;
+                let path = self.path(field)?;
+                match ImageTexture::load(&path) {
+                    Ok(texture) => {
+                        self.sources.borrow_mut().push(path);
+                        Arc::new(texture)
+                    }
+                    Err(e) => {
+                        return self.error(
+                            field.pos,
+                            format!("cannot load image `{}`: {}", path.display(), e),
+                        )
+                    }
+                }
+            }
+            _ => {
+                return self.error(
+                    v.pos,
+                    format!(
+                        "unknown texture type `{}`, expected one of: solid, checker, noise, image",
+                        kind
+                    ),
+                )
+            }
+        };
+        Ok(texture)
+    }
+
+    fn material(&self, v: &Json) -> Result<Arc<dyn Material>> {
+        match &v.value {
+            JsonValue::String(name) => match self.materials.get(name) {
+                Some(material) => Ok(material.clone()),
+                None => self.error(v.pos, format!("unknown material `{}`", name)),
+            },
+            _ => self.material_def(v),
+        }
+    }
+
+    fn material_def(&self, v: &Json) -> Result<Arc<dyn Material>> {
+        let kind = self.kind(v, "a material")?;
+        let what = format!("`{}` material", kind);
+        let material: Arc<dyn Material> = match kind {
+            "lambertian" => {
+                self.fields(v, &what, &["type", "albedo"])?;
+                Arc::new(Lambertian::construct_texture(
+                    self.texture(self.required(v, "albedo", &what)?)?,
+                ))
+            }
+            "metal" => {
+                self.fields(v, &what, &["type", "albedo", "fuzz"])?;
+                let fuzz = self.number_or(v, "fuzz", 0.0)?;
+                if fuzz < 0.0 {
+                    return self.error(v.pos, String::from("`fuzz` must not be negative"));
+                }
+                Arc::new(Metal::construct(
+                    &self.vec3(self.required(v, "albedo", &what)?)?,
+                    fuzz,
+                ))
+            }
+            "dielectric" => {
+                self.fields(v, &what, &["type", "ir", "tint", "density"])?;
+                let ir = self.positive(self.required(v, "ir", &what)?)?;
+                match v.get("tint") {
+                    Some(tint_value) => {
+                        let tint = self.vec3(tint_value)?;
+                        if tint.e.iter().any(|c| !(0.0..=1.0).contains(c)) {
+                            return self.error(
+                                tint_value.pos,
+                                String::from("`tint` components must be between 0 and 1"),
+                            );
+                        }
+                        let density = match v.get("density") {
+                            Some(density) => self.positive(density)?,
+                            None => 1.0,
+                        };
+                        Arc::new(Dielectric::construct_tinted(ir, &tint, density))
+                    }
+                    None => {
+                        if let Some(density) = v.get("density") {
+                            return self
+                                .error(density.pos, String::from("`density` needs a `tint`"));
+                        }
+                        Arc::new(Dielectric::construct(ir))
+                    }
+                }
+            }
+            "diffuse_light" => {
+                self.fields(v, &what, &["type", "emit"])?;
+                Arc::new(DiffuseLight::construct(
+                    self.texture(self.required(v, "emit", &what)?)?,
+                ))
+            }
+            "isotropic" => {
+                self.fields(v, &what, &["type", "albedo"])?;
+                Arc::new(Isotropic::construct(
+                    self.texture(self.required(v, "albedo", &what)?)?,
+                ))
+            }
+            _ => {
+                return self.error(
+                    v.pos,
+                    format!(
+                        "unknown material type `{}`, expected one of: \
+                         lambertian, metal, dielectric, diffuse_light, isotropic",
+                        kind
+                    ),
+                )
+            }
+        };
+        Ok(material)
+    }
+
+    // Reads the `[a0, a1]` pair stored under `key`, which must be increasing.
+    fn range(&self, v: &Json, key: &str, what: &str) -> Result<(f64, f64)> {
+        let field = self.required(v, key, what)?;
+        let range = self.numbers(field, 2)?;
+        if range[0] >= range[1] {
+            return self.error(field.pos, format!("`{}` must be an increasing range", key));
+        }
+        Ok((range[0], range[1]))
+    }
+
+    fn object(&self, v: &Json, time0: f64, time1: f64) -> Result<Arc<dyn Hittable>> {
+        let kind = self.kind(v, "an object")?;
+        let what = format!("`{}` object", kind);
+        let material = || self.material(self.required(v, "material", &what)?);
+        let object: Arc<dyn Hittable> = match kind {
+            "sphere" => {
+                self.fields(v, &what, &with_common(&["center", "radius", "material"]))?;
+                Arc::new(Sphere::construct(
+                    &self.vec3(self.required(v, "center", &what)?)?,
+                    self.positive(self.required(v, "radius", &what)?)?,
+                    material()?,
+                ))
+            }
+            "moving_sphere" => {
+                self.fields(
+                    v,
+                    &what,
+                    &with_common(&["center0", "center1", "time0", "time1", "radius", "material"]),
+                )?;
+                let t0 = self.number_or(v, "time0", time0)?;
+                let t1 = self.number_or(v, "time1", time1)?;
+                if t0 >= t1 {
+                    return self.error(v.pos, String::from("`time0` must be less than `time1`"));
+                }
+                Arc::new(MovingSphere::construct(
+                    &self.vec3(self.required(v, "center0", &what)?)?,
+                    &self.vec3(self.required(v, "center1", &what)?)?,
+                    t0,
+                    t1,
+                    self.positive(self.required(v, "radius", &what)?)?,
+                    material()?,
+                ))
+            }
+            "xy_rect" => {
+                self.fields(v, &what, &with_common(&["x", "y", "k", "material"]))?;
+                let (x0, x1) = self.range(v, "x", &what)?;
+                let (y0, y1) = self.range(v, "y", &what)?;
+                let k = self.number(self.required(v, "k", &what)?)?;
+                Arc::new(XyRect::construct(x0, x1, y0, y1, k, material()?))
+            }
+            "xz_rect" => {
+                self.fields(v, &what, &with_common(&["x", "z", "k", "material"]))?;
+                let (x0, x1) = self.range(v, "x", &what)?;
+                let (z0, z1) = self.range(v, "z", &what)?;
+                let k = self.number(self.required(v, "k", &what)?)?;
+                Arc::new(XzRect::construct(x0, x1, z0, z1, k, material()?))
+            }
+            "yz_rect" => {
+                self.fields(v, &what, &with_common(&["y", "z", "k", "material"]))?;
+                let (y0, y1) = self.range(v, "y", &what)?;
+                let (z0, z1) = self.range(v, "z", &what)?;
+                let k = self.number(self.required(v, "k", &what)?)?;
+                Arc::new(YzRect::construct(y0, y1, z0, z1, k, material()?))
+            }
+            "box" => {
+                self.fields(v, &what, &with_common(&["min", "max", "material"]))?;
+                let p0 = self.vec3(self.required(v, "min", &what)?)?;
+                let p1 = self.vec3(self.required(v, "max", &what)?)?;
+                if (0..3).any(|a| p0.e[a] >= p1.e[a]) {
+                    return self.error(
+                        v.pos,
+                        String::from("`min` must be smaller than `max` on every axis"),
+                    );
+                }
+                Arc::new(Box_::construct(&p0, &p1, material()?))
+            }
+            "triangle" => {
+                self.fields(
+                    v,
+                    &what,
+                    &with_common(&["vertices", "uvs", "normals", "material"]),
+                )?;
+                let field = self.required(v, "vertices", &what)?;
+                let vertices = self.array(field)?;
+                if vertices.len() != 3 {
+                    return self.error(field.pos, String::from("expected 3 vertices"));
+                }
+                let mut uv = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)];
+                if let Some(field) = v.get("uvs") {
+                    let uvs = self.array(field)?;
+                    if uvs.len() != 3 {
+                        return self.error(field.pos, String::from("expected 3 uv pairs"));
+                    }
+                    for (dst, src) in uv.iter_mut().zip(uvs) {
+                        let pair = self.numbers(src, 2)?;
+                        *dst = (pair[0], pair[1]);
+                    }
+                }
+                let triangle = Triangle::new(
+                    &self.vec3(&vertices[0])?,
+                    &self.vec3(&vertices[1])?,
+                    &self.vec3(&vertices[2])?,
+                    material()?,
+                    uv[0],
+                    uv[1],
+                    uv[2],
+                );
+                match v.get("normals") {
+                    Some(field) => {
+                        let normals = self.array(field)?;
+                        if normals.len() != 3 {
+                            return self.error(field.pos, String::from("expected 3 normals"));
+                        }
+                        Arc::new(triangle.with_normals(
+                            &self.vec3(&normals[0])?,
+                            &self.vec3(&normals[1])?,
+                            &self.vec3(&normals[2])?,
+                        ))
+                    }
+                    None => Arc::new(triangle),
+                }
+            }
+            "obj" => {
+                self.fields(
+                    v,
+                    &what,
+                    &with_common(&[
+                        "path",
+                        "name",
+                        "scale",
+                        "rotate",
+                        "translate",
+                        "material",
+                        "color",
+                        "flip_v",
+                        "crease_angle",
+                    ]),
+                )?;
+                // `name` is the older form, for the models under `objects/`.
+                let (field, obj_path) = match (v.get("path"), v.get("name")) {
+                    (Some(field), None) => (field, self.path(field)?),
+                    (None, Some(field)) => {
+                        let name = self.string(field)?;
+                        (
+                            field,
+                            PathBuf::from(format!("objects/{}/{}.obj", name, name)),
+                        )
+                    }
+                    _ => {
+                        return self.error(
+                            v.pos,
+                            format!("{} needs exactly one of `path` and `name`", what),
+                        )
+                    }
+                };
+                if !obj_path.is_file() {
+                    return self.error(field.pos, format!("cannot find `{}`", obj_path.display()));
+                }
+                let defaults = ObjOptions::default();
+                let options = ObjOptions {
+                    scale: match v.get("scale") {
+                        Some(field) => self.positive(field)?,
+                        None => defaults.scale,
+                    },
+                    rotate: self.vec3_or(v, "rotate", &defaults.rotate)?,
+                    translate: self.vec3_or(v, "translate", &defaults.translate)?,
+                    material: match v.get("material") {
+                        Some(field) => Some(self.material(field)?),
+                        None => None,
+                    },
+                    default_color: self.vec3_or(v, "color", &defaults.default_color)?,
+                    flip_v: self.boolean_or(v, "flip_v", defaults.flip_v)?,
+                    crease_angle: self.number_or(v, "crease_angle", defaults.crease_angle)?,
+                };
+                match load_obj(&obj_path, &options) {
+                    Ok(mesh) => {
+                        self.meshes
+                            .borrow_mut()
+                            .push((obj_path.clone(), mesh.to_string()));
+                        self.sources.borrow_mut().extend_from_slice(&mesh.sources);
+                        Arc::new(mesh)
+                    }
+                    Err(e) => return self.error(field.pos, e.to_string()),
+                }
+            }
+            "constant_medium" => {
+                self.fields(v, &what, &with_common(&["boundary", "density", "albedo"]))?;
+                let boundary = self.object(self.required(v, "boundary", &what)?, time0, time1)?;
+                Arc::new(ConstantMedium::construct(
+                    boundary,
+                    self.positive(self.required(v, "density", &what)?)?,
+                    self.texture(self.required(v, "albedo", &what)?)?,
+                ))
+            }
+            "list" => {
+                self.fields(v, &what, &with_common(&["objects", "bvh"]))?;
+                let field = self.required(v, "objects", &what)?;
+                let mut list = HittableList::new();
+                for item in self.array(field)? {
+                    list.add(self.object(item, time0, time1)?);
+                }
+                if list.objects.is_empty() {
+                    return self.error(field.pos, String::from("`objects` must not be empty"));
+                }
+                if self.boolean_or(v, "bvh", true)? {
+                    Arc::new(BVHNode::new(&list, time0, time1))
+                } else {
+                    Arc::new(list)
+                }
+            }
+            "instance" => {
+                self.fields(v, &what, &with_common(&["shape"]))?;
+                let field = self.required(v, "shape", &what)?;
+                let name = self.string(field)?;
+                match self.shapes.get(name) {
+                    Some(shape) => shape.clone(),
+                    None => return self.error(field.pos, format!("unknown shape `{}`", name)),
+                }
+            }
+            _ => {
+                return self.error(
+                    v.pos,
+                    format!(
+                        "unknown object type `{}`, expected one of: sphere, moving_sphere, \
+                         xy_rect, xz_rect, yz_rect, box, triangle, obj, constant_medium, list, \
+                         instance",
+                        kind
+                    ),
+                )
+            }
+        };
+        let object = match v.get("transforms") {
+            Some(transforms) => self.transforms(transforms, object)?,
+            None => object,
+        };
+        // The motion moves the object as placed by its transforms.
+        match v.get("motion") {
+            Some(motion) => Ok(Arc::new(MovingInstance::construct(
+                object,
+                self.motion(motion)?,
+            ))),
+            None => Ok(object),
+        }
+    }
+
+    // Transforms are applied in the order they are listed, and placed as one
+    // `Instance`.
+    fn transforms(&self, v: &Json, object: Arc<dyn Hittable>) -> Result<Arc<dyn Hittable>> {
+        let mut transform = Transform::identity();
+        for item in self.array(v)? {
+            let fields = self.fields(
+                item,
+                "a transform",
+                &[
+                    "translate",
+                    "rotate_x",
+                    "rotate_y",
+                    "rotate_z",
+                    "rotate",
+                    "scale",
+                    "look_at",
+                ],
+            )?;
+            if fields.len() != 1 {
+                return self.error(
+                    item.pos,
+                    String::from("each transform must have exactly one field"),
+                );
+            }
+            let (key, _, arg) = &fields[0];
+            let x_axis = Vec3::construct(&[1.0, 0.0, 0.0]);
+            let y_axis = Vec3::construct(&[0.0, 1.0, 0.0]);
+            let z_axis = Vec3::construct(&[0.0, 0.0, 1.0]);
+            let next = match key.as_str() {
+                "translate" => Transform::translate(&self.vec3(arg)?),
+                "rotate_x" => Transform::rotate(&x_axis, self.number(arg)?),
+                "rotate_y" => Transform::rotate(&y_axis, self.number(arg)?),
+                "rotate_z" => Transform::rotate(&z_axis, self.number(arg)?),
+                "rotate" => {
+                    let (axis, angle) = self.axis_angle(arg)?;
+                    Transform::rotate(&axis, angle)
+                }
+                "scale" => Transform::scale(&self.scale(arg)?),
+                _ => {
+                    self.fields(arg, "`look_at`", &["from", "to", "up"])?;
+                    let from = self.vec3(self.required(arg, "from", "`look_at`")?)?;
+                    let to = self.vec3(self.required(arg, "to", "`look_at`")?)?;
+                    let up = self.vec3_or(arg, "up", &y_axis)?;
+                    if cross(&up, &(to - from)).length_squared() == 0.0 {
+                        return self.error(
+                            arg.pos,
+                            String::from(
+                                "`from` and `to` must differ, and `up` must not point along them",
+                            ),
+                        );
+                    }
+                    Transform::look_at(&from, &to, &up)
+                }
+            };
+            transform = transform.then(&next);
+        }
+        Ok(Arc::new(Instance::construct(object, &transform)))
+    }
+
+    // `{"axis": [x, y, z], "angle": degrees}`
+    fn axis_angle(&self, v: &Json) -> Result<(Vec3, f64)> {
+        self.fields(v, "`rotate`", &["axis", "angle"])?;
+        let field = self.required(v, "axis", "`rotate`")?;
+        let axis = self.vec3(field)?;
+        if axis.length_squared() == 0.0 {
+            return self.error(field.pos, String::from("`axis` must not be zero"));
+        }
+        Ok((axis, self.number(self.required(v, "angle", "`rotate`")?)?))
+    }
+    // A single factor or one per axis.
+    fn scale(&self, v: &Json) -> Result<Vec3> {
+        let factors = match v.value {
+            JsonValue::Number(k) => Vec3::construct(&[k, k, k]),
+            _ => self.vec3(v)?,
+        };
+        if factors.e.contains(&0.0) {
+            return self.error(v.pos, String::from("`scale` must not be zero"));
+        }
+        Ok(factors)
+    }
+
+    // Keyframes in increasing order of time, each scaling, then rotating, then
+    // translating the object.
+    fn motion(&self, v: &Json) -> Result<Motion> {
+        let items = self.array(v)?;
+        if items.is_empty() {
+            return self.error(v.pos, String::from("`motion` must not be empty"));
+        }
+        let mut keyframes: Vec<Keyframe> = Vec::new();
+        for item in items {
+            self.fields(
+                item,
+                "a keyframe",
+                &["time", "scale", "rotate", "translate"],
+            )?;
+            let field = self.required(item, "time", "a keyframe")?;
+            let mut keyframe = Keyframe::construct(self.number(field)?);
+            if let Some(last) = keyframes.last() {
+                if keyframe.time <= last.time {
+                    return self
+                        .error(field.pos, String::from("keyframe times must be increasing"));
+                }
+            }
+            if let Some(scale) = item.get("scale") {
+                keyframe.scale = self.scale(scale)?;
+            }
+            // One rotation, or a list of them applied in order.
+            if let Some(rotate) = item.get("rotate") {
+                let rotations = match &rotate.value {
+                    JsonValue::Array(items) => items.as_slice(),
+                    _ => std::slice::from_ref(rotate),
+                };
+                for r in rotations {
+                    let (axis, angle) = self.axis_angle(r)?;
+                    keyframe.rotation = keyframe
+                        .rotation
+                        .then(&Quaternion::from_axis_angle(&axis, angle));
+                }
+            }
+            keyframe.translation = self.vec3_or(item, "translate", &keyframe.translation)?;
+            keyframes.push(keyframe);
+        }
+        Ok(Motion::construct(keyframes))
+    }
+}
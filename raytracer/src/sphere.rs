@@ -1,9 +1,12 @@
 use crate::aabb::Aabb;
+use crate::aarect::light_pdf;
 use crate::hittable::{HitRecord, Hittable};
 use crate::material::Material;
+use crate::onb::Onb;
 use crate::ray::Ray;
-use crate::rt_weekend::PI;
-use crate::vec3::{dot, Point3, Vec3};
+use crate::rt_weekend::{INFINITY, PI};
+use crate::sampler::sample_2d;
+use crate::vec3::{dot, random_unit_vector, Point3, Vec3};
 use std::sync::Arc;
 
 pub struct Sphere {
@@ -32,26 +35,35 @@ impl Sphere {
         *u = phi / (2.0 * PI);
         *v = theta / PI;
     }
-}
-
-impl Hittable for Sphere {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    // The nearest ray parameter in (t_min, t_max) where the ray meets the
+    // sphere.
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
         let oc: Vec3 = r.origin() - self.center;
         let a: f64 = r.direction().length_squared();
         let half_b: f64 = dot(&oc, &r.direction());
         let c: f64 = oc.length_squared() - self.radius * self.radius;
         let det: f64 = half_b * half_b - a * c;
         if det < 0.0 {
-            return false;
+            return None;
         }
         let sqrtd: f64 = det.sqrt();
         let mut root: f64 = (-half_b - sqrtd) / a;
         if root < t_min || t_max < root {
             root = (-half_b + sqrtd) / a;
             if root < t_min || t_max < root {
-                return false;
+                return None;
             }
         }
+        Some(root)
+    }
+}
+
+impl Hittable for Sphere {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let root = match self.intersect(r, t_min, t_max) {
+            Some(root) => root,
+            None => return false,
+        };
         rec.t = root;
         rec.p = r.at(rec.t);
         let outward_normal: Vec3 = (rec.p - self.center) / self.radius;
@@ -60,6 +72,9 @@ impl Hittable for Sphere {
         rec.mat_ptr = Some(Arc::clone(&self.mat_ptr));
         true
     }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
+    }
     fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
         let radius = self.radius;
         *output_box = Aabb::construct(
@@ -68,4 +83,43 @@ impl Hittable for Sphere {
         );
         true
     }
+    // Seen from outside, the sphere covers a cone of directions which is
+    // sampled uniformly. From inside, a uniform point of the surface is used.
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new();
+        if !self.hit(&Ray::construct(o, v, 0.0), 0.001, INFINITY, &mut rec) {
+            return 0.0;
+        }
+        let radius_squared = self.radius * self.radius;
+        let distance_squared = (self.center - *o).length_squared();
+        if distance_squared <= radius_squared {
+            return light_pdf(v, rec.t, &rec.normal, 4.0 * PI * radius_squared);
+        }
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+        1.0 / solid_angle
+    }
+    fn random(&self, o: &Point3) -> Vec3 {
+        let direction = self.center - *o;
+        let distance_squared = direction.length_squared();
+        if distance_squared <= self.radius * self.radius {
+            return self.center + self.radius * random_unit_vector() - *o;
+        }
+        let uvw = Onb::build_from_w(&direction);
+        uvw.local(&random_to_sphere(self.radius, distance_squared))
+    }
+    fn is_light(&self) -> bool {
+        self.mat_ptr.is_emissive()
+    }
+}
+
+// A direction, around the z axis, inside the cone covered by a sphere of
+// `radius` whose centre is at a squared distance of `distance_squared`.
+fn random_to_sphere(radius: f64, distance_squared: f64) -> Vec3 {
+    let (r1, r2) = sample_2d();
+    let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).sqrt() - 1.0);
+    let phi = 2.0 * PI * r1;
+    let x = phi.cos() * (1.0 - z * z).sqrt();
+    let y = phi.sin() * (1.0 - z * z).sqrt();
+    Vec3::construct(&[x, y, z])
 }
@@ -41,12 +41,12 @@ pub struct CheckerTexture {
     pub odd: Arc<dyn Texture>,
 }
 impl CheckerTexture {
-    // pub fn construct(ev: Arc<dyn Texture>, od: Arc<dyn Texture>) -> Self {
-    //     Self {
-    //         odd: Arc::clone(&od),
-    //         even: Arc::clone(&ev),
-    //     }
-    // }
+    pub fn construct(ev: Arc<dyn Texture>, od: Arc<dyn Texture>) -> Self {
+        Self {
+            odd: Arc::clone(&od),
+            even: Arc::clone(&ev),
+        }
+    }
     pub fn construct_color(c1: &Color3, c2: &Color3) -> Self {
         Self {
             even: Arc::new(SolidColor::construct(c1)),
@@ -102,21 +102,25 @@ pub struct ImageTexture {
 impl ImageTexture {
     pub const BYTES_PER_PIXEL: u32 = 3;
 
-    pub fn new() -> Self {
-        Self {
-            data: Arc::new(Vec::new()),
-            width: 0,
-            height: 0,
-            bytes_per_scanline: 0,
-        }
-    }
+    // pub fn new() -> Self {
+    //     Self {
+    //         data: Arc::new(Vec::new()),
+    //         width: 0,
+    //         height: 0,
+    //         bytes_per_scanline: 0,
+    //     }
+    // }
 
-    pub fn empty(&self) -> bool {
-        self.data.is_empty()
-    }
+    // pub fn empty(&self) -> bool {
+    //     self.data.is_empty()
+    // }
 
     pub fn construct(path: &str) -> Self {
-        let img = image::open(path).expect("Failed to open image");
+        Self::load(path).expect("Failed to open image")
+    }
+
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> image::ImageResult<Self> {
+        let img = image::open(path)?;
         let width: u32 = img.width();
         let height: u32 = img.height();
         let mut data: Vec<u8> = Vec::new();
@@ -127,12 +131,12 @@ impl ImageTexture {
             data.push(g);
             data.push(b);
         }
-        Self {
+        Ok(Self {
             data: Arc::new(data),
             width,
             height,
             bytes_per_scanline: width * Self::BYTES_PER_PIXEL,
-        }
+        })
     }
 }
 impl Texture for ImageTexture {
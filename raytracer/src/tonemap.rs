@@ -0,0 +1,139 @@
+// Maps linear scene radiance to display values for the 8-bit output formats.
+use crate::rt_weekend::clamp;
+use crate::vec3::{luminance, Color3};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMapper {
+    // No compression, values above 1 are clipped.
+    Clamp,
+    Reinhard,
+    // Reinhard with a white point, the luminance that maps to pure white.
+    ReinhardExtended,
+    // Krzysztof Narkowicz's fit of the ACES filmic curve.
+    Aces,
+    // John Hable's filmic curve from Uncharted 2.
+    Uncharted2,
+}
+
+impl ToneMapper {
+    pub const NAMES: [&'static str; 5] = [
+        "clamp",
+        "reinhard",
+        "reinhard-extended",
+        "aces",
+        "uncharted2",
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "clamp" => Some(Self::Clamp),
+            "reinhard" => Some(Self::Reinhard),
+            "reinhard-extended" => Some(Self::ReinhardExtended),
+            "aces" => Some(Self::Aces),
+            "uncharted2" => Some(Self::Uncharted2),
+            _ => None,
+        }
+    }
+}
+
+fn uncharted2_partial(x: f64) -> f64 {
+    const A: f64 = 0.15; // shoulder strength
+    const B: f64 = 0.50; // linear strength
+    const C: f64 = 0.10; // linear angle
+    const D: f64 = 0.20; // toe strength
+    const E: f64 = 0.02; // toe numerator
+    const F: f64 = 0.30; // toe denominator
+    ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F
+}
+
+fn aces(x: f64) -> f64 {
+    // The fit expects its input pre-exposed by 0.6.
+    let x = 0.6 * x;
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+// Scales `c` so that its luminance becomes `mapped`, keeping the hue.
+fn with_luminance(c: &Color3, l: f64, mapped: f64) -> Color3 {
+    if l <= 0.0 {
+        return Color3::new();
+    }
+    *c * (mapped / l)
+}
+
+// The sRGB transfer function (IEC 61966-2-1) for a linear value in [0,1].
+pub fn srgb_encode(x: f64) -> f64 {
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ToneMapping {
+    pub operator: ToneMapper,
+    // In stops, each one doubles the brightness.
+    pub exposure: f64,
+    // For `ReinhardExtended`; `None` uses the brightest pixel of the image.
+    pub white_point: Option<f64>,
+}
+
+impl ToneMapping {
+    // Fills in the parameters that depend on the image itself.
+    pub fn prepare(&self, pixels: &[Color3]) -> Self {
+        let mut resolved = *self;
+        if self.operator == ToneMapper::ReinhardExtended && self.white_point.is_none() {
+            let scale = self.exposure.exp2();
+            let max = pixels
+                .iter()
+                .map(|c| luminance(&(*c * scale)))
+                .filter(|l| l.is_finite())
+                .fold(0.0, f64::max);
+            resolved.white_point = Some(if max > 0.0 { max } else { 1.0 });
+        }
+        resolved
+    }
+
+    // Linear radiance to linear display values in [0,1].
+    pub fn map(&self, c: &Color3) -> Color3 {
+        let c = *c * self.exposure.exp2();
+        let mapped = match self.operator {
+            ToneMapper::Clamp => c,
+            ToneMapper::Reinhard => {
+                let l = luminance(&c);
+                with_luminance(&c, l, l / (1.0 + l))
+            }
+            ToneMapper::ReinhardExtended => {
+                let l = luminance(&c);
+                let white = self.white_point.unwrap_or(1.0);
+                with_luminance(&c, l, l * (1.0 + l / (white * white)) / (1.0 + l))
+            }
+            ToneMapper::Aces => Color3::construct(&[aces(c.x()), aces(c.y()), aces(c.z())]),
+            ToneMapper::Uncharted2 => {
+                const EXPOSURE_BIAS: f64 = 2.0;
+                const WHITE: f64 = 11.2;
+                let white_scale = 1.0 / uncharted2_partial(WHITE);
+                Color3::construct(&[
+                    uncharted2_partial(EXPOSURE_BIAS * c.x()) * white_scale,
+                    uncharted2_partial(EXPOSURE_BIAS * c.y()) * white_scale,
+                    uncharted2_partial(EXPOSURE_BIAS * c.z()) * white_scale,
+                ])
+            }
+        };
+        Color3::construct(&[
+            clamp(mapped.x(), 0.0, 1.0),
+            clamp(mapped.y(), 0.0, 1.0),
+            clamp(mapped.z(), 0.0, 1.0),
+        ])
+    }
+
+    pub fn encode_srgb8(&self, c: &Color3) -> [u8; 3] {
+        let mapped = self.map(c);
+        let quantize = |x: f64| (255.0 * srgb_encode(x) + 0.5) as u8;
+        [
+            quantize(mapped.x()),
+            quantize(mapped.y()),
+            quantize(mapped.z()),
+        ]
+    }
+}
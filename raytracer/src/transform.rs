@@ -0,0 +1,495 @@
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::hittable_list::HittableList;
+use crate::ray::Ray;
+use crate::rt_weekend::INFINITY;
+use crate::vec3::{cross, dot, Point3, Vec3};
+use std::sync::Arc;
+
+// The rows of a 3x4 matrix: the linear part, and the translation in the last
+// column.
+type Matrix = [[f64; 4]; 3];
+
+const IDENTITY: Matrix = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+];
+
+// `a` after `b`.
+fn mul(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut m = [[0.0; 4]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, x) in row.iter_mut().enumerate() {
+            *x = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+        row[3] += a[i][3];
+    }
+    m
+}
+
+fn apply_vector(m: &Matrix, v: &Vec3) -> Vec3 {
+    let row = |r: &[f64; 4]| r[0] * v.e[0] + r[1] * v.e[1] + r[2] * v.e[2];
+    Vec3::construct(&[row(&m[0]), row(&m[1]), row(&m[2])])
+}
+
+fn apply_point(m: &Matrix, p: &Point3) -> Point3 {
+    apply_vector(m, p) + Vec3::construct(&[m[0][3], m[1][3], m[2][3]])
+}
+
+// An affine transformation, kept together with its inverse so neither has to
+// be inverted while rendering.
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    m: Matrix,
+    inv: Matrix,
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Self {
+            m: IDENTITY,
+            inv: IDENTITY,
+        }
+    }
+    pub fn translate(offset: &Vec3) -> Self {
+        let mut m = IDENTITY;
+        let mut inv = IDENTITY;
+        for i in 0..3 {
+            m[i][3] = offset.e[i];
+            inv[i][3] = -offset.e[i];
+        }
+        Self { m, inv }
+    }
+    // The factors must not be zero.
+    pub fn scale(factors: &Vec3) -> Self {
+        let mut m = IDENTITY;
+        let mut inv = IDENTITY;
+        for i in 0..3 {
+            m[i][i] = factors.e[i];
+            inv[i][i] = 1.0 / factors.e[i];
+        }
+        Self { m, inv }
+    }
+    // By `angle` degrees about `axis`, counterclockwise when `axis` points at
+    // the viewer.
+    pub fn rotate(axis: &Vec3, angle: f64) -> Self {
+        let a = axis.unit();
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let mut m = IDENTITY;
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().take(3).enumerate() {
+                *x = a.e[i] * a.e[j] * (1.0 - cos);
+            }
+            row[i] += cos;
+        }
+        m[0][1] -= a.e[2] * sin;
+        m[0][2] += a.e[1] * sin;
+        m[1][0] += a.e[2] * sin;
+        m[1][2] -= a.e[0] * sin;
+        m[2][0] -= a.e[1] * sin;
+        m[2][1] += a.e[0] * sin;
+        Self {
+            m,
+            inv: Self::transpose_linear(&m),
+        }
+    }
+    // The rotation `q`, which must be a unit quaternion.
+    pub fn rotation(q: &Quaternion) -> Self {
+        let (w, x, y, z) = (q.w, q.v.x(), q.v.y(), q.v.z());
+        let m = [
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+                0.0,
+            ],
+            [
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+                0.0,
+            ],
+            [
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+                0.0,
+            ],
+        ];
+        Self {
+            m,
+            inv: Self::transpose_linear(&m),
+        }
+    }
+    // Moves the origin to `from` and turns +z towards `to`, with +y as close
+    // to `up` as it gets. `up` must not be parallel to `to - from`.
+    pub fn look_at(from: &Point3, to: &Point3, up: &Vec3) -> Self {
+        let z = (*to - *from).unit();
+        let x = cross(up, &z).unit();
+        let y = cross(&z, &x);
+        let mut m = IDENTITY;
+        for (i, row) in m.iter_mut().enumerate() {
+            *row = [x.e[i], y.e[i], z.e[i], from.e[i]];
+        }
+        let mut inv = Self::transpose_linear(&m);
+        for row in inv.iter_mut() {
+            row[3] = -(row[0] * from.e[0] + row[1] * from.e[1] + row[2] * from.e[2]);
+        }
+        Self { m, inv }
+    }
+
+    // The inverse of a rotation.
+    fn transpose_linear(m: &Matrix) -> Matrix {
+        let mut t = IDENTITY;
+        for (i, row) in t.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().take(3).enumerate() {
+                *x = m[j][i];
+            }
+        }
+        t
+    }
+
+    // `self`, then `next`.
+    pub fn then(&self, next: &Transform) -> Self {
+        Self {
+            m: mul(&next.m, &self.m),
+            inv: mul(&self.inv, &next.inv),
+        }
+    }
+    pub fn inverse(&self) -> Self {
+        Self {
+            m: self.inv,
+            inv: self.m,
+        }
+    }
+
+    pub fn point(&self, p: &Point3) -> Point3 {
+        apply_point(&self.m, p)
+    }
+    pub fn vector(&self, v: &Vec3) -> Vec3 {
+        apply_vector(&self.m, v)
+    }
+    // Normals go through the inverse transpose to stay perpendicular to the
+    // surface. The result is not normalized.
+    pub fn normal(&self, n: &Vec3) -> Vec3 {
+        let column =
+            |i: usize| self.inv[0][i] * n.e[0] + self.inv[1][i] * n.e[1] + self.inv[2][i] * n.e[2];
+        Vec3::construct(&[column(0), column(1), column(2)])
+    }
+
+    // The box around the transformed corners of `bbox`.
+    pub fn bounding_box(&self, bbox: &Aabb) -> Aabb {
+        let mut min = Point3::construct(&[INFINITY, INFINITY, INFINITY]);
+        let mut max = Point3::construct(&[-INFINITY, -INFINITY, -INFINITY]);
+        for corner in 0..8 {
+            let mut p = bbox.minimum();
+            for a in 0..3 {
+                if corner & (1 << a) != 0 {
+                    p.e[a] = bbox.maximum().e[a];
+                }
+            }
+            let p = self.point(&p);
+            for a in 0..3 {
+                min.e[a] = min.e[a].min(p.e[a]);
+                max.e[a] = max.e[a].max(p.e[a]);
+            }
+        }
+        Aabb::construct(&min, &max)
+    }
+
+    // Whether the transformation keeps angles, that is a rotation, reflection
+    // and uniform scale on top of the translation. Those keep solid angles too.
+    pub fn is_similarity(&self) -> bool {
+        let columns: Vec<Vec3> = (0..3)
+            .map(|j| Vec3::construct(&[self.m[0][j], self.m[1][j], self.m[2][j]]))
+            .collect();
+        let scale = columns[0].length_squared();
+        let tolerance = 1e-9 * scale;
+        (0..3).all(|i| {
+            (0..3).all(|j| {
+                let d = dot(&columns[i], &columns[j]);
+                let expected = if i == j { scale } else { 0.0 };
+                (d - expected).abs() <= tolerance
+            })
+        })
+    }
+}
+
+// An object placed in the world by a `Transform`. The object is shared, so a
+// mesh and its BVH can be placed many times over.
+pub struct Instance {
+    pub ptr: Arc<dyn Hittable>,
+    pub transform: Transform,
+}
+
+impl Instance {
+    pub fn construct(p: Arc<dyn Hittable>, transform: &Transform) -> Self {
+        Self {
+            ptr: p,
+            transform: *transform,
+        }
+    }
+}
+
+// The ray in the space `transform` maps from. Its direction is not normalized,
+// so the ray parameter of a hit is the same in both spaces.
+fn object_ray(transform: &Transform, r: &Ray) -> Ray {
+    let inverse = transform.inverse();
+    Ray::construct(
+        &inverse.point(&r.origin()),
+        &inverse.vector(&r.direction()),
+        r.time(),
+    )
+}
+
+fn hit_transformed(
+    ptr: &dyn Hittable,
+    transform: &Transform,
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+    rec: &mut HitRecord,
+) -> bool {
+    if !ptr.hit(&object_ray(transform, r), t_min, t_max, rec) {
+        return false;
+    }
+    // The side the ray comes from is the same in both spaces, so `front_face`
+    // stays as it is.
+    rec.p = transform.point(&rec.p);
+    rec.normal = transform.normal(&rec.normal).unit();
+    true
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        hit_transformed(&*self.ptr, &self.transform, r, t_min, t_max, rec)
+    }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.ptr
+            .occluded(&object_ray(&self.transform, r), t_min, t_max)
+    }
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut Aabb) -> bool {
+        let mut bbox = Aabb::new();
+        if !self.ptr.bounding_box(time0, time1, &mut bbox) {
+            return false;
+        }
+        *output_box = self.transform.bounding_box(&bbox);
+        true
+    }
+    // Only similarities keep the densities over solid angle of the object.
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        let inverse = self.transform.inverse();
+        self.ptr.pdf_value(&inverse.point(o), &inverse.vector(v))
+    }
+    fn random(&self, o: &Point3) -> Vec3 {
+        let inverse = self.transform.inverse();
+        self.transform.vector(&self.ptr.random(&inverse.point(o)))
+    }
+    fn is_light(&self) -> bool {
+        self.transform.is_similarity() && self.ptr.is_light()
+    }
+    // Lights under other transformations are only found by scattering.
+    fn collect_lights(&self, lights: &mut HittableList) {
+        if !self.transform.is_similarity() {
+            return;
+        }
+        let mut inner = HittableList::new();
+        self.ptr.collect_lights(&mut inner);
+        for light in inner.objects {
+            lights.add(Arc::new(Self::construct(light, &self.transform)));
+        }
+    }
+}
+
+// A rotation, as a unit quaternion.
+#[derive(Clone, Copy, Debug)]
+pub struct Quaternion {
+    pub w: f64,
+    pub v: Vec3,
+}
+
+impl Quaternion {
+    pub fn identity() -> Self {
+        Self {
+            w: 1.0,
+            v: Vec3::new(),
+        }
+    }
+    // The same rotation as `Transform::rotate(axis, angle)`.
+    pub fn from_axis_angle(axis: &Vec3, angle: f64) -> Self {
+        let (sin, cos) = (0.5 * angle.to_radians()).sin_cos();
+        Self {
+            w: cos,
+            v: sin * axis.unit(),
+        }
+    }
+    // `self`, then `next`.
+    pub fn then(&self, next: &Quaternion) -> Self {
+        Self {
+            w: next.w * self.w - dot(&next.v, &self.v),
+            v: next.w * self.v + self.w * next.v + cross(&next.v, &self.v),
+        }
+    }
+    // Spherical interpolation from `self` at 0 to `q` at 1. This does not
+    // take the shorter way round: `q` and `-q` are the same rotation, reached
+    // turning opposite ways.
+    pub fn slerp(&self, q: &Quaternion, t: f64) -> Self {
+        let cos = (self.w * q.w + dot(&self.v, &q.v)).clamp(-1.0, 1.0);
+        let (a, b) = if cos.abs() > 0.9995 {
+            (1.0 - t, t)
+        } else {
+            let theta = cos.acos();
+            let sin = theta.sin();
+            (((1.0 - t) * theta).sin() / sin, (t * theta).sin() / sin)
+        };
+        let w = a * self.w + b * q.w;
+        let v = a * self.v + b * q.v;
+        let length = (w * w + v.length_squared()).sqrt();
+        Self {
+            w: w / length,
+            v: v / length,
+        }
+    }
+    // Half the angle between the two rotations, in radians.
+    fn half_angle_to(&self, q: &Quaternion) -> f64 {
+        (self.w * q.w + dot(&self.v, &q.v)).clamp(-1.0, 1.0).acos()
+    }
+}
+
+// A pose of a `Motion`: scaled, then rotated, then translated.
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe {
+    pub time: f64,
+    pub scale: Vec3,
+    pub rotation: Quaternion,
+    pub translation: Vec3,
+}
+
+impl Keyframe {
+    pub fn construct(time: f64) -> Self {
+        Self {
+            time,
+            scale: Vec3::construct(&[1.0, 1.0, 1.0]),
+            rotation: Quaternion::identity(),
+            translation: Vec3::new(),
+        }
+    }
+    fn transform(&self) -> Transform {
+        Transform::scale(&self.scale)
+            .then(&Transform::rotation(&self.rotation))
+            .then(&Transform::translate(&self.translation))
+    }
+}
+
+// A transformation changing over time. Between keyframes, translation and
+// scale are interpolated linearly and rotation spherically; before the first
+// and after the last, the transformation holds still.
+#[derive(Clone, Debug)]
+pub struct Motion {
+    keyframes: Vec<Keyframe>,
+}
+
+impl Motion {
+    // Steps per keyframe interval when bounding the motion.
+    const BOUND_STEPS: usize = 16;
+
+    // The keyframes must be in increasing order of time, and at least one.
+    pub fn construct(keyframes: Vec<Keyframe>) -> Self {
+        if keyframes.is_empty() {
+            panic!("Motion: No keyframes");
+        }
+        Self { keyframes }
+    }
+
+    pub fn at(&self, time: f64) -> Transform {
+        let next = self.keyframes.iter().position(|k| k.time > time);
+        let (k0, k1) = match next {
+            None => return self.keyframes[self.keyframes.len() - 1].transform(),
+            Some(0) => return self.keyframes[0].transform(),
+            Some(i) => (&self.keyframes[i - 1], &self.keyframes[i]),
+        };
+        let t = (time - k0.time) / (k1.time - k0.time);
+        Keyframe {
+            time,
+            scale: (1.0 - t) * k0.scale + t * k1.scale,
+            rotation: k0.rotation.slerp(&k1.rotation, t),
+            translation: (1.0 - t) * k0.translation + t * k1.translation,
+        }
+        .transform()
+    }
+
+    // The box around `bbox` at all times in time0..time1. The transformation
+    // is sampled, and the box padded by how far the corners can stray from
+    // the straight line between two samples while rotating.
+    pub fn bounding_box(&self, bbox: &Aabb, time0: f64, time1: f64) -> Aabb {
+        let mut times = vec![time0, time1];
+        let mut pad: f64 = 0.0;
+        // The farthest corner from the origin, which the rotation is about.
+        let corner = (0..3)
+            .map(|a| {
+                let x = bbox.minimum().e[a].abs().max(bbox.maximum().e[a].abs());
+                x * x
+            })
+            .sum::<f64>()
+            .sqrt();
+        for pair in self.keyframes.windows(2) {
+            let (k0, k1) = (&pair[0], &pair[1]);
+            if k1.time <= time0 || k0.time >= time1 {
+                continue;
+            }
+            for step in 0..=Self::BOUND_STEPS {
+                let t = k0.time + (k1.time - k0.time) * step as f64 / Self::BOUND_STEPS as f64;
+                if time0 < t && t < time1 {
+                    times.push(t);
+                }
+            }
+            let max_scale = (0..3)
+                .map(|a| k0.scale.e[a].abs().max(k1.scale.e[a].abs()))
+                .fold(0.0, f64::max);
+            let step_angle =
+                2.0 * k0.rotation.half_angle_to(&k1.rotation) / Self::BOUND_STEPS as f64;
+            pad = pad.max(corner * max_scale * (1.0 - (0.5 * step_angle).cos()));
+        }
+
+        let mut out = self.at(times[0]).bounding_box(bbox);
+        for &t in &times[1..] {
+            out = Aabb::surrounding_box(&out, &self.at(t).bounding_box(bbox));
+        }
+        let pad = Vec3::construct(&[pad, pad, pad]);
+        Aabb::construct(&(out.minimum() - pad), &(out.maximum() + pad))
+    }
+}
+
+// An object moving by a `Motion`, placed according to each ray's time.
+pub struct MovingInstance {
+    pub ptr: Arc<dyn Hittable>,
+    pub motion: Motion,
+}
+
+impl MovingInstance {
+    pub fn construct(p: Arc<dyn Hittable>, motion: Motion) -> Self {
+        Self { ptr: p, motion }
+    }
+}
+
+// Light sampling has no time to place the object with, so moving lights are
+// only found by scattering.
+impl Hittable for MovingInstance {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let transform = self.motion.at(r.time());
+        hit_transformed(&*self.ptr, &transform, r, t_min, t_max, rec)
+    }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        let transform = self.motion.at(r.time());
+        self.ptr.occluded(&object_ray(&transform, r), t_min, t_max)
+    }
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut Aabb) -> bool {
+        let mut bbox = Aabb::new();
+        if !self.ptr.bounding_box(time0, time1, &mut bbox) {
+            return false;
+        }
+        *output_box = self.motion.bounding_box(&bbox, time0, time1);
+        true
+    }
+}
@@ -1,10 +1,11 @@
 use crate::aabb::Aabb;
+use crate::aarect::light_pdf;
 use crate::hittable::{HitRecord, Hittable};
 use crate::material::Material;
 use crate::ray::Ray;
-// use crate::rt_weekend::{random_double, random_double_range};
+use crate::rt_weekend::INFINITY;
+use crate::sampler::sample_2d;
 use crate::vec3::*;
-// use std::f64::INFINITY;
 use std::sync::Arc;
 
 #[derive(Clone)]
@@ -14,6 +15,9 @@ pub struct Triangle {
     pub pb: Vec3,
     pub pc: Vec3,
     //pc perpendicular to ac with length of ac/2*area
+    pub ab: Vec3,
+    pub ac: Vec3,
+    pub area: f64,
     pub bbox: Aabb,
     pub mat: Arc<dyn Material>,
 
@@ -21,6 +25,9 @@ pub struct Triangle {
     pub uv_a: (f64, f64),
     pub uv_ab: (f64, f64),
     pub uv_ac: (f64, f64),
+
+    // Vertex normals at a, b and c, for smooth shading.
+    pub normals: Option<[Vec3; 3]>,
 }
 
 impl Triangle {
@@ -49,26 +56,44 @@ impl Triangle {
             n,
             pb: cross(&n, &ab) / area2,
             pc: cross(&ac, &n) / area2,
+            ab,
+            ac,
+            area: area2 / 2.0,
             mat,
             bbox: Aabb::construct(&min, &max),
             uv_a: (ua, va),
             uv_ab: (ub - ua, vb - va),
             uv_ac: (uc - ua, vc - va),
+            normals: None,
+        }
+    }
+    // Shades with normals interpolated from the vertices. They are flipped,
+    // all together, to the side of the face normal if they point away from it.
+    pub fn with_normals(mut self, na: &Vec3, nb: &Vec3, nc: &Vec3) -> Self {
+        if [na, nb, nc].iter().any(|n| n.length_squared() == 0.0) {
+            return self;
         }
+        let (mut na, mut nb, mut nc) = (na.unit(), nb.unit(), nc.unit());
+        if dot(&(na + nb + nc), &self.n) < 0.0 {
+            na = -na;
+            nb = -nb;
+            nc = -nc;
+        }
+        self.normals = Some([na, nb, nc]);
+        self
     }
     pub fn to_texture_coord(&self, u0: f64, v0: f64) -> (f64, f64) {
         let u = self.uv_a.0 + self.uv_ab.0 * u0 + self.uv_ac.0 * v0;
         let v = self.uv_a.1 + self.uv_ab.1 * u0 + self.uv_ac.1 * v0;
         (u, v)
     }
-}
-
-impl Hittable for Triangle {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    // The ray parameter, point and barycentric coordinates (AP = uAB + vAC)
+    // of the hit, if in (t_min, t_max).
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, Point3, f64, f64)> {
         let oa = self.a - r.origin();
         let t = dot(&oa, &self.n) / dot(&r.direction(), &self.n);
         if t < t_min || t_max < t {
-            return false;
+            return None;
         }
         let p = r.at(t);
         let ap = p - self.a;
@@ -76,25 +101,59 @@ impl Hittable for Triangle {
         let v = dot(&ap, &self.pb);
         // AP = uAB + vAC
         if u >= 0.0 && v >= 0.0 && u + v <= 1.0 {
-            let (x, y) = self.to_texture_coord(u, v);
-            *rec = HitRecord {
-                p,
-                normal: self.n,
-                t,
-                u: x,
-                v: y,
-                front_face: true, //set it true if you want to emit light!!!
-                mat_ptr: Some(Arc::clone(&self.mat)),
-            };
-            // rec.set_face_normal(r, &self.n);
-            true
+            Some((t, p, u, v))
         } else {
-            false
+            None
         }
     }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let (t, p, u, v) = match self.intersect(r, t_min, t_max) {
+            Some(hit) => hit,
+            None => return false,
+        };
+        let (x, y) = self.to_texture_coord(u, v);
+        rec.p = p;
+        rec.t = t;
+        rec.u = x;
+        rec.v = y;
+        rec.mat_ptr = Some(Arc::clone(&self.mat));
+        // The side is decided by the geometric normal, the smooth one only
+        // shades.
+        rec.set_face_normal(r, &self.n);
+        if let Some([na, nb, nc]) = &self.normals {
+            let shading = ((1.0 - u - v) * *na + u * *nb + v * *nc).unit();
+            rec.normal = if rec.front_face { shading } else { -shading };
+        }
+        true
+    }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
+    }
 
     fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
         *output_box = self.bbox;
         true
     }
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new();
+        if !self.hit(&Ray::construct(o, v, 0.0), 0.001, INFINITY, &mut rec) {
+            return 0.0;
+        }
+        light_pdf(v, rec.t, &self.n, self.area)
+    }
+    fn random(&self, o: &Point3) -> Vec3 {
+        let (mut u, mut v) = sample_2d();
+        // Fold the far half of the parallelogram back onto the triangle.
+        if u + v > 1.0 {
+            u = 1.0 - u;
+            v = 1.0 - v;
+        }
+        self.a + u * self.ab + v * self.ac - *o
+    }
+    fn is_light(&self) -> bool {
+        self.mat.is_emissive()
+    }
 }
@@ -0,0 +1,197 @@
+use crate::aabb::Aabb;
+use crate::bvh::IndexBvh;
+use crate::hittable::{HitRecord, Hittable};
+use crate::hittable_list::HittableList;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::triangle::Triangle;
+use crate::vec3::*;
+use std::sync::Arc;
+
+// One triangle of a `TriangleMesh`, as indices into the mesh's buffers.
+#[derive(Clone, Copy, Debug)]
+pub struct MeshTriangle {
+    pub positions: [u32; 3],
+    pub normals: Option<[u32; 3]>,
+    pub uvs: Option<[u32; 3]>,
+    pub material: u32,
+}
+
+// Triangles sharing vertex, normal and texture coordinate buffers. A triangle
+// costs a few indices instead of a `Triangle` of its own, and the mesh keeps
+// its own BVH over them.
+pub struct TriangleMesh {
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f64, f64)>,
+    triangles: Vec<MeshTriangle>,
+    materials: Vec<Arc<dyn Material>>,
+    bvh: IndexBvh,
+}
+
+impl TriangleMesh {
+    const LEAF_SIZE: usize = 4;
+
+    pub fn construct(
+        positions: Vec<Point3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<(f64, f64)>,
+        triangles: Vec<MeshTriangle>,
+        materials: Vec<Arc<dyn Material>>,
+    ) -> Self {
+        let boxes: Vec<Aabb> = triangles
+            .iter()
+            .map(|tri| {
+                let [a, b, c] = tri.positions.map(|i| positions[i as usize]);
+                let mut min = Point3::default();
+                let mut max = Point3::default();
+                for i in 0..3 {
+                    min.e[i] = a.e[i].min(b.e[i]).min(c.e[i]) - 0.0001;
+                    max.e[i] = a.e[i].max(b.e[i]).max(c.e[i]) + 0.0001;
+                }
+                Aabb::construct(&min, &max)
+            })
+            .collect();
+        let bvh = IndexBvh::construct(&boxes, Self::LEAF_SIZE);
+        Self {
+            positions,
+            normals,
+            uvs,
+            triangles,
+            materials,
+            bvh,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.triangles.len()
+    }
+
+    fn vertices(&self, tri: &MeshTriangle) -> [Point3; 3] {
+        tri.positions.map(|i| self.positions[i as usize])
+    }
+
+    // Möller-Trumbore. The ray parameter and the barycentric coordinates of
+    // the second and third vertices, if hit in (t_min, t_max).
+    fn intersect(&self, index: usize, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+        let [a, b, c] = self.vertices(&self.triangles[index]);
+        let ab = b - a;
+        let ac = c - a;
+        let pvec = cross(&r.direction(), &ac);
+        let det = dot(&ab, &pvec);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let tvec = r.origin() - a;
+        let u = dot(&tvec, &pvec) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let qvec = cross(&tvec, &ab);
+        let v = dot(&r.direction(), &qvec) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = dot(&ac, &qvec) * inv_det;
+        if t < t_min || t_max < t {
+            return None;
+        }
+        Some((t, u, v))
+    }
+
+    fn hit_triangle(
+        &self,
+        index: usize,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        rec: &mut HitRecord,
+    ) -> bool {
+        let (t, u, v) = match self.intersect(index, r, t_min, t_max) {
+            Some(hit) => hit,
+            None => return false,
+        };
+        let tri = &self.triangles[index];
+        let [a, b, c] = self.vertices(tri);
+        rec.t = t;
+        rec.p = r.at(t);
+        let n = cross(&(b - a), &(c - a)).unit();
+        rec.set_face_normal(r, &n);
+        if let Some([na, nb, nc]) = tri.normals {
+            let shading = (1.0 - u - v) * self.normals[na as usize]
+                + u * self.normals[nb as usize]
+                + v * self.normals[nc as usize];
+            if shading.length_squared() > 0.0 {
+                let mut shading = shading.unit();
+                if dot(&shading, &n) < 0.0 {
+                    shading = -shading;
+                }
+                rec.normal = if rec.front_face { shading } else { -shading };
+            }
+        }
+        let (tu, tv) = match tri.uvs {
+            Some([ta, tb, tc]) => {
+                let [ta, tb, tc] = [ta, tb, tc].map(|i| self.uvs[i as usize]);
+                (
+                    (1.0 - u - v) * ta.0 + u * tb.0 + v * tc.0,
+                    (1.0 - u - v) * ta.1 + u * tb.1 + v * tc.1,
+                )
+            }
+            None => (u, v),
+        };
+        rec.u = tu;
+        rec.v = tv;
+        rec.mat_ptr = Some(Arc::clone(&self.materials[tri.material as usize]));
+        true
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        self.bvh.hit(r, t_min, t_max, &mut |index, closest| {
+            if self.hit_triangle(index, r, t_min, closest, rec) {
+                Some(rec.t)
+            } else {
+                None
+            }
+        })
+    }
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.bvh.occluded(r, t_min, t_max, &mut |index| {
+            self.intersect(index, r, t_min, t_max).is_some()
+        })
+    }
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut Aabb) -> bool {
+        *output_box = self.bvh.bounding_box();
+        true
+    }
+    // Emissive triangles are handed out as standalone `Triangle`s, which can
+    // be sampled. There are usually few of them.
+    fn collect_lights(&self, lights: &mut HittableList) {
+        for tri in &self.triangles {
+            let material = &self.materials[tri.material as usize];
+            if !material.is_emissive() {
+                continue;
+            }
+            let [a, b, c] = self.vertices(tri);
+            let (ua, ub, uc) = match tri.uvs {
+                Some([ta, tb, tc]) => (
+                    self.uvs[ta as usize],
+                    self.uvs[tb as usize],
+                    self.uvs[tc as usize],
+                ),
+                None => ((0.0, 0.0), (1.0, 0.0), (0.0, 1.0)),
+            };
+            let triangle = Triangle::new(&a, &b, &c, material.clone(), ua, ub, uc);
+            lights.add(Arc::new(match tri.normals {
+                Some([na, nb, nc]) => triangle.with_normals(
+                    &self.normals[na as usize],
+                    &self.normals[nb as usize],
+                    &self.normals[nc as usize],
+                ),
+                None => triangle,
+            }));
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use crate::rt_weekend::random_double;
 use crate::rt_weekend::random_double_range;
+use crate::rt_weekend::PI;
+use crate::sampler::{sample_1d, sample_2d};
 use std::fmt;
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
 
@@ -76,18 +78,18 @@ pub fn cross(u: &Vec3, v: &Vec3) -> Vec3 {
     }
 }
 
+// The random points and directions below are warped from the current sample,
+// see `sampler`. Rejection sampling would waste how evenly it is spread.
 pub fn random_in_unit_sphere() -> Vec3 {
-    loop {
-        let p: Vec3 = Vec3::random_range(-1.0, 1.0);
-        if p.length_squared() >= 1.0 {
-            continue;
-        }
-        return p;
-    }
+    sample_1d().cbrt() * random_unit_vector()
 }
 
 pub fn random_unit_vector() -> Vec3 {
-    random_in_unit_sphere().unit()
+    let (u1, u2) = sample_2d();
+    let z = 1.0 - 2.0 * u1;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u2;
+    Vec3::construct(&[r * phi.cos(), r * phi.sin(), z])
 }
 
 // pub fn random_in_hemisphere(normal: &Vec3) -> Vec3 {
@@ -99,18 +101,20 @@ pub fn random_unit_vector() -> Vec3 {
 //     }
 // }
 
+// Shirley and Chiu's concentric map, which keeps neighbouring samples close.
 pub fn random_in_unit_disk() -> Vec3 {
-    loop {
-        let p: Vec3 = Vec3::construct(&[
-            random_double_range(-1.0, 1.0),
-            random_double_range(-1.0, 1.0),
-            0.0,
-        ]);
-        if p.length_squared() >= 1.0 {
-            continue;
-        }
-        return p;
+    let (u1, u2) = sample_2d();
+    let a = 2.0 * u1 - 1.0;
+    let b = 2.0 * u2 - 1.0;
+    if a == 0.0 && b == 0.0 {
+        return Vec3::new();
     }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, PI / 4.0 * (b / a))
+    } else {
+        (b, PI / 2.0 - PI / 4.0 * (a / b))
+    };
+    Vec3::construct(&[r * theta.cos(), r * theta.sin(), 0.0])
 }
 
 pub fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
@@ -244,6 +248,11 @@ impl DivAssign<f64> for Vec3 {
 pub type Point3 = Vec3;
 pub type Color3 = Vec3;
 
+// Relative luminance of a linear Rec. 709 color.
+pub fn luminance(c: &Color3) -> f64 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
+
 pub fn refract(uv: &Vec3, n: &Vec3, etai_over_etat: f64) -> Vec3 {
     let cos_theta: f64 = dot(&(-*uv), n).min(1.0);
     let r_out_perp = etai_over_etat * (*uv + cos_theta * *n);